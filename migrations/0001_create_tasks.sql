@@ -0,0 +1,6 @@
+-- Initial schema, matching databases set up by hand before migrations existed
+CREATE TABLE IF NOT EXISTS tasks (
+  task_id SERIAL PRIMARY KEY,
+  name VARCHAR NOT NULL,
+  priority INT
+);
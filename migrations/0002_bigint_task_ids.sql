@@ -0,0 +1,3 @@
+-- Widen task ids so generated ids (e.g. Snowflake) fit
+ALTER TABLE tasks ALTER COLUMN task_id TYPE BIGINT;
+ALTER SEQUENCE tasks_task_id_seq AS BIGINT;
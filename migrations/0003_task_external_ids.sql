@@ -0,0 +1,3 @@
+-- Client-supplied ids for integrations syncing tasks from other systems
+ALTER TABLE tasks ADD COLUMN external_id VARCHAR;
+CREATE UNIQUE INDEX tasks_external_id_key ON tasks (external_id);
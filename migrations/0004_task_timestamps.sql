@@ -0,0 +1,34 @@
+-- Creation/modification times, used for Last-Modified on task lists
+ALTER TABLE tasks
+  ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE FUNCTION tasks_touch_updated_at() RETURNS trigger AS $$
+BEGIN
+  NEW.updated_at = now();
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER tasks_touch_updated_at
+  BEFORE UPDATE ON tasks
+  FOR EACH ROW EXECUTE FUNCTION tasks_touch_updated_at();
+
+-- Deleted rows leave no updated_at behind, so remember when the last
+-- delete happened to keep Last-Modified moving forward
+CREATE TABLE task_list_changes (
+  id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id),
+  last_deleted_at TIMESTAMPTZ
+);
+INSERT INTO task_list_changes (id) VALUES (true);
+
+CREATE FUNCTION tasks_record_delete() RETURNS trigger AS $$
+BEGIN
+  UPDATE task_list_changes SET last_deleted_at = now();
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER tasks_record_delete
+  AFTER DELETE ON tasks
+  FOR EACH STATEMENT EXECUTE FUNCTION tasks_record_delete();
@@ -0,0 +1,4 @@
+-- Workers claim tasks from GET /tasks/next?claim=true
+ALTER TABLE tasks
+  ADD COLUMN claimed_by VARCHAR,
+  ADD COLUMN claimed_at TIMESTAMPTZ;
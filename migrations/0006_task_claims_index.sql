@@ -0,0 +1,5 @@
+-- no-transaction
+-- Finds the next unclaimed task without sorting the whole table
+CREATE INDEX CONCURRENTLY tasks_next_unclaimed
+  ON tasks (priority DESC NULLS LAST, created_at, task_id)
+  WHERE claimed_by IS NULL;
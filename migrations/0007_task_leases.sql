@@ -0,0 +1,4 @@
+-- Claims expire unless renewed; completed tasks stay claimed by their worker
+ALTER TABLE tasks
+  ADD COLUMN lease_expires_at TIMESTAMPTZ,
+  ADD COLUMN completed_at TIMESTAMPTZ;
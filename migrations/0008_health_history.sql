@@ -0,0 +1,13 @@
+-- Periodic health checks, kept as a ring buffer: each check overwrites
+-- the slot the sequence points at next, so the table never grows past the
+-- configured number of slots
+CREATE SEQUENCE health_history_seq;
+
+CREATE TABLE health_history (
+  slot BIGINT PRIMARY KEY,
+  checked_at TIMESTAMPTZ NOT NULL,
+  db_latency_ms DOUBLE PRECISION NOT NULL,
+  job_lag_ms BIGINT NOT NULL,
+  requests BIGINT NOT NULL,
+  server_errors BIGINT NOT NULL
+);
@@ -0,0 +1,20 @@
+-- An update can move a row out of a filtered list, where its updated_at is
+-- no longer seen, so remember when the last one changed a filtered column
+ALTER TABLE task_list_changes ADD COLUMN last_moved_at TIMESTAMPTZ;
+
+CREATE FUNCTION tasks_record_move() RETURNS trigger AS $$
+BEGIN
+  UPDATE task_list_changes SET last_moved_at = now();
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER tasks_record_move
+  AFTER UPDATE ON tasks
+  FOR EACH ROW
+  WHEN (
+    OLD.name IS DISTINCT FROM NEW.name
+    OR OLD.priority IS DISTINCT FROM NEW.priority
+    OR OLD.external_id IS DISTINCT FROM NEW.external_id
+  )
+  EXECUTE FUNCTION tasks_record_move();
@@ -0,0 +1,52 @@
+-- Rows leaving the task list, by a delete or by an update of a column the
+-- list filters on, leave a tombstone with their old values. Last-Modified
+-- then applies the same filters to tombstones as to tasks, so a write only
+-- moves the lists it touches. The triggers run once per statement, and
+-- pruned tombstones raise a single floor instead of one row per write.
+DROP TRIGGER tasks_record_move ON tasks;
+DROP FUNCTION tasks_record_move();
+DROP TRIGGER tasks_record_delete ON tasks;
+DROP FUNCTION tasks_record_delete();
+ALTER TABLE task_list_changes DROP COLUMN last_moved_at;
+-- deletes so far have no tombstones, so the last one is the first floor
+ALTER TABLE task_list_changes RENAME COLUMN last_deleted_at TO pruned_until;
+
+CREATE TABLE task_list_tombstones (
+  task_id BIGINT NOT NULL,
+  name VARCHAR NOT NULL,
+  priority INT,
+  external_id VARCHAR,
+  removed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX task_list_tombstones_removed_at ON task_list_tombstones (removed_at);
+
+CREATE FUNCTION tasks_record_delete() RETURNS trigger AS $$
+BEGIN
+  INSERT INTO task_list_tombstones (task_id, name, priority, external_id)
+  SELECT task_id, name, priority, external_id FROM old_rows;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER tasks_record_delete
+  AFTER DELETE ON tasks
+  REFERENCING OLD TABLE AS old_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION tasks_record_delete();
+
+CREATE FUNCTION tasks_record_move() RETURNS trigger AS $$
+BEGIN
+  INSERT INTO task_list_tombstones (task_id, name, priority, external_id)
+  SELECT o.task_id, o.name, o.priority, o.external_id
+  FROM old_rows o
+  JOIN new_rows n USING (task_id)
+  WHERE o.name IS DISTINCT FROM n.name
+    OR o.priority IS DISTINCT FROM n.priority
+    OR o.external_id IS DISTINCT FROM n.external_id;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER tasks_record_move
+  AFTER UPDATE ON tasks
+  REFERENCING OLD TABLE AS old_rows NEW TABLE AS new_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION tasks_record_move();
@@ -0,0 +1,4 @@
+-- Due dates and tags, set by quick add ("Pay rent tomorrow 5pm #finance")
+ALTER TABLE tasks
+  ADD COLUMN due_at TIMESTAMPTZ,
+  ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,50 @@
+// Admin-only routes
+//
+// Everything under /admin requires `Authorization: Bearer <ADMIN_TOKEN>`.
+// When ADMIN_TOKEN is not configured the admin area is disabled entirely.
+
+// Imports
+use axum::{
+  extract::{Request, State},
+  http::{header, StatusCode},
+  middleware::Next,
+  response::Response,
+};
+
+use serde_json::json;
+
+use subtle::ConstantTimeEq;
+
+use crate::AppState;
+
+// Functions
+pub async fn require_admin(
+  State(state): State<AppState>,
+  req: Request,
+  next: Next,
+) -> Result<Response, (StatusCode, String)> {
+  let Some(admin_token) = state.admin_token.as_deref() else {
+    return Err((
+      StatusCode::NOT_FOUND,
+      json!({"success": false, "message": "Admin API is disabled"}).to_string(),
+    ));
+  };
+
+  let bearer = req
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "));
+
+  // compared in constant time, so response timing does not leak the token
+  let authorized =
+    bearer.is_some_and(|bearer| bool::from(bearer.as_bytes().ct_eq(admin_token.as_bytes())));
+  if !authorized {
+    return Err((
+      StatusCode::UNAUTHORIZED,
+      json!({"success": false, "message": "Admin token required"}).to_string(),
+    ));
+  }
+
+  Ok(next.run(req).await)
+}
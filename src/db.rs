@@ -0,0 +1,133 @@
+// Database pool helpers
+//
+// `warm_up` opens the pool's minimum connections eagerly at startup.
+//
+// `begin` starts a transaction whose statement_timeout is the time left
+// before the request deadline (see the deadline module), `commit` finishes
+// it, and `error_response` maps query errors, including a blown deadline, to
+// HTTP errors. Both count towards the request's Server-Timing db time.
+//
+// `retry_read` deals with primary failover. During a failover existing
+// connections are cut or end up on a node that has been demoted to
+// read-only, and getting a connection times out or fails; idempotent reads
+// close the connection that hit such an error and try again on a fresh one. New connections resolve the database host
+// again, so they reach the new primary once DNS has been updated.
+
+// Imports
+use axum::http::StatusCode;
+
+use serde_json::json;
+
+use sqlx::{Connection, PgConnection, PgPool, Postgres, Transaction};
+
+use std::time::Duration;
+
+use crate::{deadline, timing};
+
+// Constants
+const MAX_READ_ATTEMPTS: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+// Functions
+pub async fn retry_read<T>(
+  pg_pool: &PgPool,
+  read: impl AsyncFn(&mut PgConnection) -> Result<T, sqlx::Error>,
+) -> Result<T, sqlx::Error> {
+  let mut attempt = 1;
+
+  loop {
+    // acquiring is retried too, it is where a failover usually shows first
+    let mut conn = None;
+    let result = timing::db(async {
+      let conn = conn.insert(pg_pool.acquire().await?);
+      let mut tx = conn.begin().await?;
+      apply_deadline(&mut tx).await?;
+      let value = read(&mut tx).await?;
+      tx.commit().await?;
+      Ok(value)
+    })
+    .await;
+
+    match result {
+      Err(e) if attempt < MAX_READ_ATTEMPTS && is_failover_error(&e) => {
+        eprintln!(
+          "{}",
+          json!({
+            "event": "db_failover_retry",
+            "attempt": attempt,
+            "error": e.to_string(),
+          })
+        );
+
+        if let Some(conn) = conn {
+          let _ = conn.close().await;
+        }
+        tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt - 1)).await;
+        attempt += 1;
+      }
+      result => return result,
+    }
+  }
+}
+
+pub async fn begin(pg_pool: &PgPool) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+  timing::db(async {
+    let mut tx = pg_pool.begin().await?;
+    apply_deadline(&mut tx).await?;
+    Ok(tx)
+  })
+  .await
+}
+
+pub async fn commit(tx: Transaction<'static, Postgres>) -> Result<(), sqlx::Error> {
+  timing::db(tx.commit()).await
+}
+
+async fn apply_deadline(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+  let Some(remaining) = deadline::remaining() else {
+    return Ok(());
+  };
+
+  // a statement_timeout of 0 would disable the timeout altogether, and
+  // Postgres rejects values past i32::MAX milliseconds
+  let timeout_ms = remaining.as_millis().clamp(1, i32::MAX as u128).to_string();
+  sqlx::query("SELECT set_config('statement_timeout', $1, true)")
+    .bind(timeout_ms)
+    .execute(conn)
+    .await?;
+
+  Ok(())
+}
+
+pub fn error_response(e: sqlx::Error) -> (StatusCode, String) {
+  let cancelled = matches!(&e, sqlx::Error::Database(db) if db.code().as_deref() == Some("57014"));
+  if cancelled && deadline::remaining().is_some() {
+    return deadline::deadline_exceeded();
+  }
+
+  (
+    StatusCode::INTERNAL_SERVER_ERROR,
+    json!({"success": false, "message": e.to_string()}).to_string(),
+  )
+}
+
+pub async fn warm_up(pg_pool: &PgPool, connections: u32) -> Result<(), sqlx::Error> {
+  let mut conns = Vec::new();
+  for _ in 0..connections {
+    conns.push(pg_pool.acquire().await?);
+  }
+
+  Ok(())
+}
+
+pub fn is_failover_error(e: &sqlx::Error) -> bool {
+  match e {
+    sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut => true,
+    sqlx::Error::Database(db) => db.code().is_some_and(|code| {
+      // 08: connection exceptions, 57P01-03: server shutting down or
+      // starting up, 25006: connected to a read-only (demoted) node
+      code.starts_with("08") || matches!(&*code, "57P01" | "57P02" | "57P03" | "25006")
+    }),
+    _ => false,
+  }
+}
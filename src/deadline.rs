@@ -0,0 +1,95 @@
+// Per-request deadlines
+//
+// Clients can bound how long a request may take with either
+// `X-Request-Deadline: <unix epoch milliseconds>` or a gRPC style
+// `grpc-timeout: <value><unit>` (at most 8 digits, then H, M, S, m, u or
+// n). Values that are malformed or too far out to represent are ignored,
+// as if the header was not sent. The remaining budget
+// becomes the statement_timeout of the request's database transactions, and
+// a query cancelled by it answers 504 with code `deadline_exceeded`.
+
+// Imports
+use axum::{
+  extract::Request,
+  http::{HeaderMap, StatusCode},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+
+use serde_json::json;
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+tokio::task_local! {
+  static DEADLINE: Instant;
+}
+
+// Functions
+pub async fn propagate_deadline(req: Request, next: Next) -> Response {
+  let Some(deadline) = requested_deadline(req.headers()) else {
+    return next.run(req).await;
+  };
+
+  if deadline <= Instant::now() {
+    return deadline_exceeded().into_response();
+  }
+
+  DEADLINE.scope(deadline, next.run(req)).await
+}
+
+// Time left before the current request's deadline, if it has one
+pub fn remaining() -> Option<Duration> {
+  DEADLINE
+    .try_with(|deadline| deadline.saturating_duration_since(Instant::now()))
+    .ok()
+}
+
+pub fn deadline_exceeded() -> (StatusCode, String) {
+  (
+    StatusCode::GATEWAY_TIMEOUT,
+    json!({
+      "success": false,
+      "code": "deadline_exceeded",
+      "message": "The request deadline was exceeded",
+    })
+    .to_string(),
+  )
+}
+
+fn requested_deadline(headers: &HeaderMap) -> Option<Instant> {
+  let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+  if let Some(deadline_ms) = header("x-request-deadline").and_then(|v| v.trim().parse::<u64>().ok())
+  {
+    let now_ms = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .ok()?
+      .as_millis() as u64;
+    return Instant::now().checked_add(Duration::from_millis(deadline_ms.saturating_sub(now_ms)));
+  }
+
+  header("grpc-timeout")
+    .and_then(parse_grpc_timeout)
+    .and_then(|timeout| Instant::now().checked_add(timeout))
+}
+
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+  let value = value.trim();
+  let unit_len = value.chars().last()?.len_utf8();
+  let (amount, unit) = value.split_at(value.len() - unit_len);
+  // the gRPC spec allows at most 8 digits
+  if amount.is_empty() || amount.len() > 8 || !amount.bytes().all(|b| b.is_ascii_digit()) {
+    return None;
+  }
+  let amount: u64 = amount.parse().ok()?;
+
+  match unit {
+    "H" => Some(Duration::from_secs(amount.checked_mul(3600)?)),
+    "M" => Some(Duration::from_secs(amount.checked_mul(60)?)),
+    "S" => Some(Duration::from_secs(amount)),
+    "m" => Some(Duration::from_millis(amount)),
+    "u" => Some(Duration::from_micros(amount)),
+    "n" => Some(Duration::from_nanos(amount)),
+    _ => None,
+  }
+}
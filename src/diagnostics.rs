@@ -0,0 +1,310 @@
+// Query plan capture for slow requests
+//
+// Handlers run their queries through `traced_query!` / `traced_query_as!` /
+// `traced_query_scalar!`, which record the statement and its parameters for
+// the current request (and count it for the timing module).
+// When a request takes longer than SLOW_REQUEST_MS (and is picked by
+// SLOW_REQUEST_SAMPLE_RATE, default 0.01) its queries are re-run under
+// `EXPLAIN (ANALYZE, BUFFERS)` inside a transaction that is rolled back, and
+// the plans are kept in a small in-memory log served at
+// GET /admin/query-plans.
+//
+// Slow requests often mean a slow database, so capturing must not add much
+// to its load: at most MAX_IN_FLIGHT captures run at once (the rest are
+// skipped), each plan gets EXPLAIN_TIMEOUT, and only SELECTs are explained,
+// since ANALYZE executes the statement and a re-run write would still use up
+// sequence values and fire triggers.
+
+// Imports
+use axum::{
+  extract::{Request, State},
+  http::StatusCode,
+  middleware::Next,
+  response::Response,
+};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use sqlx::{PgPool, Postgres};
+
+use tokio::sync::Semaphore;
+
+use std::{
+  borrow::Cow,
+  collections::VecDeque,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use axum_crud_rest_types::timestamp::Timestamp;
+
+use crate::{response::Reply, timing, AppState};
+
+// Constants
+const MAX_CAPTURES: usize = 100;
+const MAX_IN_FLIGHT: usize = 2;
+const EXPLAIN_TIMEOUT: &str = "5s";
+
+tokio::task_local! {
+  static QUERIES: Arc<Mutex<Vec<RecordedQuery>>>;
+}
+
+// Macros
+macro_rules! traced_query {
+  ($sql:literal $(, $arg:expr)* $(,)?) => {{
+    $crate::diagnostics::record($sql, vec![$($crate::diagnostics::PlanParam::from(&$arg)),*]);
+    sqlx::query!($sql $(, $arg)*)
+  }};
+}
+
+macro_rules! traced_query_as {
+  ($out:ty, $sql:literal $(, $arg:expr)* $(,)?) => {{
+    $crate::diagnostics::record($sql, vec![$($crate::diagnostics::PlanParam::from(&$arg)),*]);
+    sqlx::query_as!($out, $sql $(, $arg)*)
+  }};
+}
+
+macro_rules! traced_query_scalar {
+  ($sql:literal $(, $arg:expr)* $(,)?) => {{
+    $crate::diagnostics::record($sql, vec![$($crate::diagnostics::PlanParam::from(&$arg)),*]);
+    sqlx::query_scalar!($sql $(, $arg)*)
+  }};
+}
+
+pub(crate) use {traced_query, traced_query_as, traced_query_scalar};
+
+// Store
+pub struct Diagnostics {
+  threshold: Option<Duration>,
+  sample_rate: f64,
+  captures: Mutex<VecDeque<CapturedRequest>>,
+  in_flight: Arc<Semaphore>,
+}
+
+impl Diagnostics {
+  pub fn new(threshold: Option<Duration>, sample_rate: f64) -> Self {
+    Self {
+      threshold,
+      sample_rate,
+      captures: Mutex::new(VecDeque::new()),
+      in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+    }
+  }
+
+  // captured requests, newest first
+  pub fn recent(&self) -> Vec<CapturedRequest> {
+    self
+      .captures
+      .lock()
+      .unwrap()
+      .iter()
+      .rev()
+      .cloned()
+      .collect()
+  }
+
+  fn push(&self, capture: CapturedRequest) {
+    let mut captures = self.captures.lock().unwrap();
+    if captures.len() == MAX_CAPTURES {
+      captures.pop_front();
+    }
+    captures.push_back(capture);
+  }
+}
+
+// Functions
+pub fn record(sql: impl Into<Cow<'static, str>>, params: Vec<PlanParam>) {
+  timing::count_query();
+
+  let sql = sql.into();
+  let _ = QUERIES.try_with(|queries| queries.lock().unwrap().push(RecordedQuery { sql, params }));
+}
+
+pub async fn capture_slow_requests(
+  State(state): State<AppState>,
+  req: Request,
+  next: Next,
+) -> Response {
+  let Some(threshold) = state.diagnostics.threshold else {
+    return next.run(req).await;
+  };
+
+  let method = req.method().to_string();
+  let path = req.uri().path().to_owned();
+  let queries = Arc::new(Mutex::new(Vec::new()));
+
+  let started = Instant::now();
+  let res = QUERIES.scope(queries.clone(), next.run(req)).await;
+  let elapsed = started.elapsed();
+
+  if elapsed >= threshold && rand::random::<f64>() < state.diagnostics.sample_rate {
+    let queries = std::mem::take(&mut *queries.lock().unwrap());
+    let slot = state.diagnostics.in_flight.clone().try_acquire_owned();
+    if let (false, Ok(slot)) = (queries.is_empty(), slot) {
+      tokio::spawn(async move {
+        let _slot = slot;
+        let plans = explain_all(&state.db_pool, &queries).await;
+        state.diagnostics.push(CapturedRequest {
+          method,
+          path,
+          duration_ms: elapsed.as_millis() as u64,
+          captured_at: Timestamp::now(),
+          queries: plans,
+        });
+      });
+    }
+  }
+
+  res
+}
+
+pub async fn get_query_plans(
+  State(state): State<AppState>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  Ok(reply.data(StatusCode::OK, state.diagnostics.recent()))
+}
+
+async fn explain_all(pg_pool: &PgPool, queries: &[RecordedQuery]) -> Vec<CapturedPlan> {
+  let mut plans = Vec::new();
+
+  for query in queries {
+    let plan = if is_select(&query.sql) {
+      explain(pg_pool, query)
+        .await
+        .unwrap_or_else(|e| json!({ "error": e.to_string() }))
+    } else {
+      json!({ "skipped": "only SELECT statements are explained" })
+    };
+
+    plans.push(CapturedPlan {
+      sql: query.sql.clone(),
+      plan,
+    });
+  }
+
+  plans
+}
+
+// A WITH can hide a write, so only statements starting with SELECT count
+fn is_select(sql: &str) -> bool {
+  sql
+    .trim_start()
+    .get(..6)
+    .is_some_and(|keyword| keyword.eq_ignore_ascii_case("select"))
+}
+
+// EXPLAIN ANALYZE executes the statement; the transaction is rolled back
+// regardless.
+async fn explain(pg_pool: &PgPool, query: &RecordedQuery) -> Result<Value, sqlx::Error> {
+  let mut tx = pg_pool.begin().await?;
+  sqlx::query("SELECT set_config('statement_timeout', $1, true)")
+    .bind(EXPLAIN_TIMEOUT)
+    .execute(&mut *tx)
+    .await?;
+
+  let sql = format!("EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON) {}", query.sql);
+  let mut explain = sqlx::query_scalar::<Postgres, Value>(&sql);
+  for param in &query.params {
+    explain = match param {
+      PlanParam::Int(v) => explain.bind(*v),
+      PlanParam::BigInt(v) => explain.bind(*v),
+      PlanParam::Text(v) => explain.bind(v.clone()),
+      PlanParam::IntArray(v) => explain.bind(v.clone()),
+      PlanParam::BigIntArray(v) => explain.bind(v.clone()),
+      PlanParam::TextArray(v) => explain.bind(v.clone()),
+    };
+  }
+
+  let plan = explain.fetch_one(&mut *tx).await?;
+  tx.rollback().await?;
+
+  Ok(plan)
+}
+
+// Structs
+pub struct RecordedQuery {
+  sql: Cow<'static, str>,
+  params: Vec<PlanParam>,
+}
+
+#[derive(Clone)]
+pub enum PlanParam {
+  Int(Option<i32>),
+  BigInt(Option<i64>),
+  Text(Option<String>),
+  IntArray(Vec<i32>),
+  BigIntArray(Vec<i64>),
+  TextArray(Vec<String>),
+}
+
+impl From<&i32> for PlanParam {
+  fn from(v: &i32) -> Self {
+    PlanParam::Int(Some(*v))
+  }
+}
+
+impl From<&Option<i32>> for PlanParam {
+  fn from(v: &Option<i32>) -> Self {
+    PlanParam::Int(*v)
+  }
+}
+
+impl From<&i64> for PlanParam {
+  fn from(v: &i64) -> Self {
+    PlanParam::BigInt(Some(*v))
+  }
+}
+
+impl From<&Option<i64>> for PlanParam {
+  fn from(v: &Option<i64>) -> Self {
+    PlanParam::BigInt(*v)
+  }
+}
+
+impl From<&String> for PlanParam {
+  fn from(v: &String) -> Self {
+    PlanParam::Text(Some(v.clone()))
+  }
+}
+
+impl From<&Option<String>> for PlanParam {
+  fn from(v: &Option<String>) -> Self {
+    PlanParam::Text(v.clone())
+  }
+}
+
+#[derive(Clone, Serialize)]
+pub struct CapturedRequest {
+  method: String,
+  path: String,
+  duration_ms: u64,
+  captured_at: Timestamp,
+  queries: Vec<CapturedPlan>,
+}
+
+#[derive(Clone, Serialize)]
+struct CapturedPlan {
+  sql: Cow<'static, str>,
+  plan: Value,
+}
+
+// array parameters are passed to the query macros by reference
+impl From<&&Vec<i32>> for PlanParam {
+  fn from(v: &&Vec<i32>) -> Self {
+    PlanParam::IntArray(v.to_vec())
+  }
+}
+
+impl From<&&Vec<i64>> for PlanParam {
+  fn from(v: &&Vec<i64>) -> Self {
+    PlanParam::BigIntArray(v.to_vec())
+  }
+}
+
+impl From<&&Vec<String>> for PlanParam {
+  fn from(v: &&Vec<String>) -> Self {
+    PlanParam::TextArray(v.to_vec())
+  }
+}
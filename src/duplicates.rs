@@ -0,0 +1,228 @@
+// Duplicate POST detection
+//
+// A double-clicked submit button or a client retrying too eagerly sends
+// the same POST twice within moments. For the routes listed in
+// DUPLICATE_WINDOWS (`<route>=<secs>` pairs such as `/tasks=5,/tasks/quick=5`,
+// empty and so off by default) a POST with the same body from the same
+// client within the route's window does not run again: it gets the
+// original response, headers included, with a 200 and `"duplicate": true`
+// added to the body. Requests carry no user identity, so a client is its IP
+// address and User-Agent; the headers that shape the response (Accept,
+// X-Date-Format, X-Response-Envelope) are part of the match too. Clients
+// behind one NAT with the same User-Agent look alike, so only opt in routes
+// where two identical POSTs moments apart are never intended.
+//
+// A duplicate that arrives while the original is still running waits for
+// it. Only successful responses are replayed; after an error the next
+// identical request runs normally.
+
+// Imports
+use axum::{
+  body::{self, Body, Bytes},
+  extract::{ConnectInfo, MatchedPath, Request, State},
+  http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+
+use serde_json::{json, Value};
+
+use sha2::{Digest, Sha256};
+
+use tokio::sync::watch;
+
+use std::{
+  collections::HashMap,
+  net::SocketAddr,
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+use crate::{feature_usage, AppState};
+
+// Constants
+const MAX_BODY: usize = 2 * 1024 * 1024;
+
+// Store
+pub struct Duplicates {
+  windows: HashMap<String, Duration>,
+  seen: Mutex<HashMap<[u8; 32], Seen>>,
+}
+
+impl Duplicates {
+  pub fn from_config(windows: &str) -> Self {
+    let windows = windows
+      .split(',')
+      .map(str::trim)
+      .filter(|pair| !pair.is_empty())
+      .map(|pair| {
+        let (route, secs) = pair
+          .split_once('=')
+          .and_then(|(route, secs)| Some((route.trim(), secs.trim().parse().ok()?)))
+          .unwrap_or_else(|| {
+            panic!("Invalid DUPLICATE_WINDOWS entry {pair:?}, expected <route>=<secs>")
+          });
+        (route.to_owned(), Duration::from_secs(secs))
+      })
+      .collect();
+
+    Self {
+      windows,
+      seen: Mutex::new(HashMap::new()),
+    }
+  }
+}
+
+// Functions
+pub async fn detect(State(state): State<AppState>, req: Request, next: Next) -> Response {
+  let window = req
+    .extensions()
+    .get::<MatchedPath>()
+    .filter(|_| req.method() == Method::POST)
+    .and_then(|route| state.duplicates.windows.get(route.as_str()))
+    .copied();
+  let Some(window) = window else {
+    return next.run(req).await;
+  };
+
+  let (parts, body) = req.into_parts();
+  let Ok(body) = body::to_bytes(body, MAX_BODY).await else {
+    return (
+      StatusCode::PAYLOAD_TOO_LARGE,
+      json!({"success": false, "message": "Request body is too large"}).to_string(),
+    )
+      .into_response();
+  };
+
+  let header = |name: &str| {
+    parts
+      .headers
+      .get(name)
+      .map_or(&b""[..], HeaderValue::as_bytes)
+  };
+  let client = parts
+    .extensions
+    .get::<ConnectInfo<SocketAddr>>()
+    .map(|ConnectInfo(addr)| addr.ip().to_string())
+    .unwrap_or_default();
+
+  let mut hash = Sha256::new();
+  for part in [
+    client.as_bytes(),
+    header(header::USER_AGENT.as_str()),
+    header(header::ACCEPT.as_str()),
+    header("x-date-format"),
+    header("x-response-envelope"),
+    parts.uri.path().as_bytes(),
+    &body,
+  ] {
+    hash.update((part.len() as u64).to_be_bytes());
+    hash.update(part);
+  }
+  let key: [u8; 32] = hash.finalize().into();
+
+  let entry = {
+    let mut seen = state.duplicates.seen.lock().unwrap();
+    seen.retain(|_, entry| entry.at.elapsed() < entry.window);
+    match seen.get(&key) {
+      Some(entry) => Entry::Duplicate(entry.response.clone()),
+      None => {
+        let (tx, rx) = watch::channel(None);
+        seen.insert(
+          key,
+          Seen {
+            at: Instant::now(),
+            window,
+            response: rx,
+          },
+        );
+        Entry::Original(tx)
+      }
+    }
+  };
+
+  match entry {
+    Entry::Original(tx) => {
+      let res = next.run(Request::from_parts(parts, Body::from(body))).await;
+      store(&state, key, tx, res).await
+    }
+    // the original either finishes with a stored response or gives up,
+    // which closes the channel
+    Entry::Duplicate(mut original) => {
+      let stored = original
+        .wait_for(Option::is_some)
+        .await
+        .ok()
+        .and_then(|stored| stored.clone());
+      match stored {
+        Some(stored) => replay(&stored),
+        None => next.run(Request::from_parts(parts, Body::from(body))).await,
+      }
+    }
+  }
+}
+
+async fn store(
+  state: &AppState,
+  key: [u8; 32],
+  tx: watch::Sender<Option<Stored>>,
+  res: Response,
+) -> Response {
+  if !res.status().is_success() {
+    state.duplicates.seen.lock().unwrap().remove(&key);
+    return res;
+  }
+
+  let (parts, body) = res.into_parts();
+  let Ok(body) = body::to_bytes(body, usize::MAX).await else {
+    state.duplicates.seen.lock().unwrap().remove(&key);
+    return (
+      StatusCode::INTERNAL_SERVER_ERROR,
+      json!({"success": false, "message": "Response body could not be read"}).to_string(),
+    )
+      .into_response();
+  };
+
+  tx.send_replace(Some(Stored {
+    headers: parts.headers.clone(),
+    body: body.clone(),
+  }));
+
+  Response::from_parts(parts, Body::from(body))
+}
+
+fn replay(stored: &Stored) -> Response {
+  feature_usage::used("duplicate_replay");
+
+  let body = match serde_json::from_slice::<Value>(&stored.body) {
+    Ok(Value::Object(mut fields)) => {
+      fields.insert("duplicate".to_owned(), Value::Bool(true));
+      Bytes::from(Value::Object(fields).to_string())
+    }
+    _ => stored.body.clone(),
+  };
+
+  let mut res = (StatusCode::OK, body).into_response();
+  *res.headers_mut() = stored.headers.clone();
+  // the body changed, so its length is set afresh
+  res.headers_mut().remove(header::CONTENT_LENGTH);
+  res
+}
+
+// Structs
+enum Entry {
+  Original(watch::Sender<Option<Stored>>),
+  Duplicate(watch::Receiver<Option<Stored>>),
+}
+
+struct Seen {
+  at: Instant,
+  window: Duration,
+  response: watch::Receiver<Option<Stored>>,
+}
+
+#[derive(Clone)]
+struct Stored {
+  headers: HeaderMap,
+  body: Bytes,
+}
@@ -0,0 +1,108 @@
+// Envelope usage telemetry
+//
+// Counts, per client, how many success responses went out in the
+// `{"success": ..., "data": ...}` envelope and how many raw, and whether the
+// envelope was asked for explicitly or came by default. Clients are told
+// apart by User-Agent, as requests carry no API key. The report at
+// GET /admin/envelope-usage shows who would notice if the default changed.
+//
+// At most MAX_CLIENTS user agents are tracked; later ones are counted
+// under `other`.
+
+// Imports
+use axum::{extract::State, http::StatusCode, response::Response};
+
+use serde::Serialize;
+
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+};
+
+use axum_crud_rest_types::timestamp::Timestamp;
+
+use crate::response::Reply;
+
+// Constants
+const MAX_CLIENTS: usize = 1000;
+const OTHER_CLIENTS: &str = "other";
+
+// Store
+pub struct EnvelopeUsage {
+  clients: Mutex<HashMap<String, ClientUsage>>,
+}
+
+impl EnvelopeUsage {
+  pub fn new() -> Self {
+    Self {
+      clients: Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub fn record(&self, client: &str, usage: Usage) {
+    let mut clients = self.clients.lock().unwrap();
+
+    let client = if clients.contains_key(client) || clients.len() < MAX_CLIENTS {
+      client
+    } else {
+      OTHER_CLIENTS
+    };
+    let counts = clients.entry(client.to_owned()).or_default();
+
+    match usage {
+      Usage::DefaultEnvelope => counts.default_envelope += 1,
+      Usage::ExplicitEnvelope => counts.explicit_envelope += 1,
+      Usage::Raw => counts.raw += 1,
+    }
+    counts.last_seen = Timestamp::now();
+  }
+}
+
+// Functions
+pub async fn get_envelope_usage(
+  State(usage): State<Arc<EnvelopeUsage>>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  // copied out, since sending the report records usage too
+  let mut report: Vec<ClientReport> = usage
+    .clients
+    .lock()
+    .unwrap()
+    .iter()
+    .map(|(client, usage)| ClientReport {
+      client: client.clone(),
+      usage: usage.clone(),
+    })
+    .collect();
+  report.sort_by(|a, b| {
+    let enveloped = |r: &ClientReport| r.usage.default_envelope + r.usage.explicit_envelope;
+    enveloped(b)
+      .cmp(&enveloped(a))
+      .then(a.client.cmp(&b.client))
+  });
+
+  Ok(reply.data(StatusCode::OK, report))
+}
+
+// Structs
+#[derive(Clone, Copy)]
+pub enum Usage {
+  DefaultEnvelope,
+  ExplicitEnvelope,
+  Raw,
+}
+
+#[derive(Clone, Default, Serialize)]
+struct ClientUsage {
+  default_envelope: u64,
+  explicit_envelope: u64,
+  raw: u64,
+  last_seen: Timestamp,
+}
+
+#[derive(Serialize)]
+struct ClientReport {
+  client: String,
+  #[serde(flatten)]
+  usage: ClientUsage,
+}
@@ -0,0 +1,222 @@
+// Admin data explorer
+//
+// Read-only views of an allowlisted set of tables for support staff, so
+// investigating a report does not need psql access:
+// - GET /admin/tables lists the tables with their filters and sort fields
+// - GET /admin/tables/:name returns rows, taking the same `limit`,
+//   `offset`, `sort` and `<filter>=<value>` parameters as the list
+//   endpoints (see the list_params module)
+//
+// Each table's schema decides which columns are shown. Masked columns only
+// show whether they are set, and cannot be filtered or sorted on. Values
+// are returned as Postgres writes them in JSON.
+
+// Imports
+use axum::{
+  extract::{FromRequestParts, Path, State},
+  http::{request::Parts, StatusCode},
+  response::Response,
+};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use sqlx::{FromRow, PgPool};
+
+use crate::{
+  db,
+  list_params::{Filter, FilterKind, ListParams, ListSchema, Sort},
+  response::Reply,
+};
+
+// Constants
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+// Traits
+trait Table: ListSchema {
+  const NAME: &'static str;
+  const MASKED: &'static [&'static str];
+}
+
+// Functions
+pub async fn list_tables(reply: Reply) -> Result<Response, (StatusCode, String)> {
+  Ok(reply.data(
+    StatusCode::OK,
+    [describe::<TasksTable>(), describe::<MigrationsTable>()],
+  ))
+}
+
+pub async fn get_table(
+  State(pg_pool): State<PgPool>,
+  Path(name): Path<String>,
+  mut parts: Parts,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  let rows = match name.as_str() {
+    TasksTable::NAME => read::<TasksTable>(&pg_pool, &mut parts).await?,
+    MigrationsTable::NAME => read::<MigrationsTable>(&pg_pool, &mut parts).await?,
+    _ => {
+      return Err((
+        StatusCode::NOT_FOUND,
+        json!({"success": false, "message": "Table not found"}).to_string(),
+      ))
+    }
+  };
+
+  Ok(reply.data(StatusCode::OK, rows))
+}
+
+async fn read<T: Table>(
+  pg_pool: &PgPool,
+  parts: &mut Parts,
+) -> Result<Vec<ExplorerRow>, (StatusCode, String)> {
+  let list = ListParams::<T>::from_request_parts(parts, &()).await?;
+
+  let limit = list.limit.unwrap_or(DEFAULT_LIMIT);
+  if limit > MAX_LIMIT {
+    return Err((
+      StatusCode::UNPROCESSABLE_ENTITY,
+      json!({"success": false, "message": format!("`limit` must be at most {MAX_LIMIT}")})
+        .to_string(),
+    ));
+  }
+
+  let query = list.query(limit, None);
+  db::retry_read(pg_pool, async move |conn| query.fetch_all(conn).await)
+    .await
+    .map_err(db::error_response)
+}
+
+fn describe<T: Table>() -> TableReport {
+  TableReport {
+    name: T::NAME,
+    filters: T::FILTERS.iter().map(|filter| filter.name).collect(),
+    sorts: T::SORTS.iter().map(|sort| sort.name).collect(),
+    masked: T::MASKED,
+  }
+}
+
+// Structs
+#[derive(FromRow, Serialize)]
+#[serde(transparent)]
+struct ExplorerRow {
+  row: Value,
+}
+
+#[derive(Serialize)]
+struct TableReport {
+  name: &'static str,
+  filters: Vec<&'static str>,
+  sorts: Vec<&'static str>,
+  masked: &'static [&'static str],
+}
+
+struct TasksTable;
+
+impl ListSchema for TasksTable {
+  const SELECT: &'static str = "
+    SELECT jsonb_build_object(
+      'task_id', task_id,
+      'name', name,
+      'priority', priority,
+      'external_id', CASE WHEN external_id IS NULL THEN NULL ELSE 'masked' END,
+      'created_at', created_at,
+      'updated_at', updated_at,
+      'claimed_by', claimed_by,
+      'claimed_at', claimed_at,
+      'lease_expires_at', lease_expires_at,
+      'completed_at', completed_at
+    ) AS row
+    FROM tasks";
+  const SORTS: &'static [Sort] = &[
+    Sort {
+      name: "task_id",
+      column: "task_id",
+      text: false,
+    },
+    Sort {
+      name: "priority",
+      column: "priority",
+      text: false,
+    },
+    Sort {
+      name: "created_at",
+      column: "created_at",
+      text: false,
+    },
+    Sort {
+      name: "updated_at",
+      column: "updated_at",
+      text: false,
+    },
+  ];
+  const TIEBREAK: &'static str = "task_id";
+  const FILTERS: &'static [Filter] = &[
+    Filter {
+      name: "task_id",
+      column: "task_id",
+      kind: FilterKind::BigInt,
+    },
+    Filter {
+      name: "priority",
+      column: "priority",
+      kind: FilterKind::Int,
+    },
+    Filter {
+      name: "name",
+      column: "name",
+      kind: FilterKind::Contains,
+    },
+    Filter {
+      name: "claimed_by",
+      column: "claimed_by",
+      kind: FilterKind::Text,
+    },
+  ];
+  const EXTRA: &'static [&'static str] = &[];
+}
+
+impl Table for TasksTable {
+  const NAME: &'static str = "tasks";
+  // ids in the systems tasks were imported from
+  const MASKED: &'static [&'static str] = &["external_id"];
+}
+
+struct MigrationsTable;
+
+impl ListSchema for MigrationsTable {
+  const SELECT: &'static str = "
+    SELECT jsonb_build_object(
+      'version', version,
+      'description', description,
+      'installed_on', installed_on,
+      'success', success,
+      'execution_time', execution_time
+    ) AS row
+    FROM _sqlx_migrations";
+  const SORTS: &'static [Sort] = &[
+    Sort {
+      name: "version",
+      column: "version",
+      text: false,
+    },
+    Sort {
+      name: "installed_on",
+      column: "installed_on",
+      text: false,
+    },
+  ];
+  const TIEBREAK: &'static str = "version";
+  const FILTERS: &'static [Filter] = &[Filter {
+    name: "description",
+    column: "description",
+    kind: FilterKind::Contains,
+  }];
+  const EXTRA: &'static [&'static str] = &[];
+}
+
+impl Table for MigrationsTable {
+  const NAME: &'static str = "migrations";
+  const MASKED: &'static [&'static str] = &[];
+}
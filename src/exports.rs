@@ -0,0 +1,335 @@
+// Asynchronous exports of the tasks table (CSV or JSON backup)
+//
+// POST /exports starts an export operation that runs in the background.
+// Its status is reported by GET /operations/:id, whose result link is a
+// time-limited signed download URL pointing at GET /exports/:id/download.
+//
+// Finished files are kept for EXPORT_RETENTION_SECS (default 3600) and then
+// dropped by the `expire_exports` job. Download URLs never outlive the file,
+// and once it is gone the operation no longer has a result link.
+
+// Imports
+use axum::{
+  extract::{Path, Query, State},
+  http::{header, HeaderValue, StatusCode},
+  response::{IntoResponse, Response},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use sqlx::PgPool;
+
+use std::{
+  collections::HashMap,
+  sync::Mutex,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum_crud_rest_types::{routes, TaskRow};
+
+use crate::{
+  extract::{JsonBody, KnownFields},
+  operations::{Cancelled, OperationHandle, OperationKind},
+  response::Reply,
+  AppState,
+};
+
+// Aliases
+type HmacSha256 = Hmac<Sha256>;
+
+// Constants
+pub const EXPIRE_INTERVAL: Duration = Duration::from_secs(60);
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+// Store
+pub struct Exports {
+  files: Mutex<HashMap<u64, ExportFile>>,
+  signing_key: Vec<u8>,
+  url_ttl: Duration,
+  retention: Duration,
+}
+
+impl Exports {
+  pub fn new(signing_key: Vec<u8>, url_ttl: Duration, retention: Duration) -> Self {
+    Self {
+      files: Mutex::new(HashMap::new()),
+      signing_key,
+      url_ttl,
+      retention,
+    }
+  }
+
+  // Drops the files whose retention has passed
+  pub fn expire(&self) {
+    let now = unix_now();
+    self
+      .files
+      .lock()
+      .unwrap()
+      .retain(|_, file| file.expires_at > now);
+  }
+
+  fn mac(&self, export_id: u64, expires: u64) -> HmacSha256 {
+    let mut mac =
+      HmacSha256::new_from_slice(&self.signing_key).expect("HMAC accepts any key length");
+    mac.update(format!("{export_id}:{expires}").as_bytes());
+    mac
+  }
+
+  fn sign(&self, export_id: u64, expires: u64) -> String {
+    hex::encode(self.mac(export_id, expires).finalize().into_bytes())
+  }
+
+  fn verify(&self, export_id: u64, expires: u64, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+      return false;
+    };
+
+    self
+      .mac(export_id, expires)
+      .verify_slice(&signature)
+      .is_ok()
+  }
+
+  // None once the file has expired
+  pub fn download_url(&self, export_id: u64) -> Option<String> {
+    let now = unix_now();
+    let file_expires_at = self.files.lock().unwrap().get(&export_id)?.expires_at;
+    if file_expires_at <= now {
+      return None;
+    }
+    let expires = (now + self.url_ttl.as_secs()).min(file_expires_at);
+    let signature = self.sign(export_id, expires);
+    Some(format!(
+      "{}?expires={expires}&signature={signature}",
+      routes::export_download(export_id)
+    ))
+  }
+}
+
+// Functions
+pub async fn create_export(
+  State(state): State<AppState>,
+  reply: Reply,
+  JsonBody(req, warnings): JsonBody<CreateExportReq>,
+) -> Result<Response, (StatusCode, String)> {
+  let format = req.format.unwrap_or(ExportFormat::Csv);
+  let operation = state.operations.start(OperationKind::Export);
+  let operation_id = operation.id();
+
+  tokio::spawn(async move {
+    if operation.is_cancelled() {
+      return;
+    }
+    operation.running();
+
+    match render_export(&state.db_pool, format, &operation).await {
+      Ok(data) => {
+        let file = ExportFile {
+          format,
+          data,
+          expires_at: unix_now() + state.exports.retention.as_secs(),
+        };
+        state
+          .exports
+          .files
+          .lock()
+          .unwrap()
+          .insert(operation.id(), file);
+        operation.succeed();
+      }
+      Err(ExportError::Cancelled(_)) => operation.cancelled(),
+      Err(ExportError::Database(e)) => operation.fail(e.to_string()),
+    }
+  });
+
+  let mut res = reply
+    .warn(warnings)
+    .data(StatusCode::ACCEPTED, json!({"operation_id": operation_id}));
+  res.headers_mut().insert(
+    header::LOCATION,
+    HeaderValue::from_str(&routes::operation(operation_id)).unwrap(),
+  );
+
+  Ok(res)
+}
+
+pub async fn download_export(
+  State(state): State<AppState>,
+  Path(export_id): Path<u64>,
+  Query(params): Query<DownloadParams>,
+) -> Result<Response, (StatusCode, String)> {
+  if params.expires < unix_now()
+    || !state
+      .exports
+      .verify(export_id, params.expires, &params.signature)
+  {
+    return Err((
+      StatusCode::FORBIDDEN,
+      json!({"success": false, "message": "Download link is invalid or has expired"}).to_string(),
+    ));
+  }
+
+  let files = state.exports.files.lock().unwrap();
+  let file = files.get(&export_id).ok_or_else(not_found)?;
+
+  let filename = format!("tasks-export-{export_id}.{}", file.format.extension());
+
+  Ok(
+    (
+      [
+        (header::CONTENT_TYPE, file.format.content_type().to_owned()),
+        (
+          header::CONTENT_DISPOSITION,
+          format!("attachment; filename=\"{filename}\""),
+        ),
+      ],
+      file.data.clone(),
+    )
+      .into_response(),
+  )
+}
+
+// Rows are read in keyset-paginated batches; cancellation is honoured
+// between batches and discards whatever was rendered so far.
+async fn render_export(
+  pg_pool: &PgPool,
+  format: ExportFormat,
+  operation: &OperationHandle,
+) -> Result<Vec<u8>, ExportError> {
+  let total = sqlx::query_scalar!("SELECT COUNT(*) FROM tasks")
+    .fetch_one(pg_pool)
+    .await?
+    .unwrap_or(0) as u64;
+
+  let mut rows: Vec<TaskRow> = Vec::new();
+  loop {
+    let last_task_id = rows.last().map_or(0, |row| row.task_id);
+    let batch = sqlx::query_as!(
+      TaskRow,
+      "SELECT task_id, name, priority, external_id FROM tasks WHERE task_id > $1 ORDER BY task_id LIMIT $2",
+      last_task_id,
+      EXPORT_BATCH_SIZE
+    )
+    .fetch_all(pg_pool)
+    .await?;
+
+    let done = batch.is_empty();
+    rows.extend(batch);
+    operation.checkpoint(rows.len() as u64, total)?;
+
+    if done {
+      break;
+    }
+  }
+
+  let data = match format {
+    ExportFormat::Csv => {
+      let mut csv = String::from("task_id,name,priority,external_id\n");
+      for row in &rows {
+        let priority = row.priority.map(|p| p.to_string()).unwrap_or_default();
+        let external_id = row.external_id.as_deref().unwrap_or_default();
+        csv.push_str(&format!(
+          "{},{},{},{}\n",
+          row.task_id,
+          csv_field(&row.name),
+          priority,
+          csv_field(external_id)
+        ));
+      }
+      csv.into_bytes()
+    }
+    ExportFormat::Json => json!({ "tasks": rows }).to_string().into_bytes(),
+  };
+
+  Ok(data)
+}
+
+fn csv_field(value: &str) -> String {
+  if value.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value.to_owned()
+  }
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .expect("System clock is before the unix epoch")
+    .as_secs()
+}
+
+fn not_found() -> (StatusCode, String) {
+  (
+    StatusCode::NOT_FOUND,
+    json!({"success": false, "message": "Export not found"}).to_string(),
+  )
+}
+
+// Structs
+enum ExportError {
+  Database(sqlx::Error),
+  Cancelled(Cancelled),
+}
+
+impl From<sqlx::Error> for ExportError {
+  fn from(e: sqlx::Error) -> Self {
+    ExportError::Database(e)
+  }
+}
+
+impl From<Cancelled> for ExportError {
+  fn from(e: Cancelled) -> Self {
+    ExportError::Cancelled(e)
+  }
+}
+
+struct ExportFile {
+  format: ExportFormat,
+  data: Vec<u8>,
+  // unix seconds
+  expires_at: u64,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+  Csv,
+  Json,
+}
+
+impl ExportFormat {
+  fn extension(self) -> &'static str {
+    match self {
+      ExportFormat::Csv => "csv",
+      ExportFormat::Json => "json",
+    }
+  }
+
+  fn content_type(self) -> &'static str {
+    match self {
+      ExportFormat::Csv => "text/csv",
+      ExportFormat::Json => "application/json",
+    }
+  }
+}
+
+#[derive(Deserialize)]
+pub struct CreateExportReq {
+  format: Option<ExportFormat>,
+}
+
+impl KnownFields for CreateExportReq {
+  const FIELDS: &'static [&'static str] = &["format"];
+}
+
+#[derive(Deserialize)]
+pub struct DownloadParams {
+  expires: u64,
+  signature: String,
+}
@@ -0,0 +1,261 @@
+// JSON request bodies with unknown-field checking
+//
+// `JsonBody<T>` replaces `Json<T>` for request bodies. Fields that `T` does
+// not know about are handled according to JSON_MODE:
+// - `lenient` (default): they are ignored and reported back as warnings
+// - `strict`: the request is rejected with 422 listing them
+//
+// This catches client typos such as `prioirty` that would otherwise be
+// silently dropped.
+//
+// Bodies are also checked for pathological shapes before they reach `T`,
+// each rejected with a 422 naming the problem:
+// - nesting deeper than JSON_MAX_DEPTH levels (default 32)
+// - arrays longer than `T::MAX_ARRAY_LEN` (default 100; bulk endpoints
+//   raise it)
+// - objects with the same key twice, which would otherwise keep only the
+//   last value
+// Malformed JSON is a 400, and the body size is capped by axum's default
+// 2 MB limit.
+
+// Imports
+use axum::{
+  async_trait,
+  body::Bytes,
+  extract::{FromRef, FromRequest, Request},
+  http::{header, StatusCode},
+};
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Deserializer, MapAccess, SeqAccess};
+use serde_json::{json, Map, Number, Value};
+
+use std::fmt;
+
+use crate::feature_usage;
+
+// Constants
+const DEFAULT_MAX_ARRAY_LEN: usize = 100;
+
+// Traits
+pub trait KnownFields {
+  const FIELDS: &'static [&'static str];
+  const MAX_ARRAY_LEN: usize = DEFAULT_MAX_ARRAY_LEN;
+}
+
+// Extractor
+pub struct JsonBody<T>(pub T, pub Vec<String>);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for JsonBody<T>
+where
+  T: DeserializeOwned + KnownFields,
+  JsonMode: FromRef<S>,
+  JsonMaxDepth: FromRef<S>,
+  S: Send + Sync,
+{
+  type Rejection = (StatusCode, String);
+
+  async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+    let content_type = req
+      .headers()
+      .get(header::CONTENT_TYPE)
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.split(';').next())
+      .map(str::trim)
+      .unwrap_or_default();
+    if !(content_type == "application/json" || content_type.ends_with("+json")) {
+      return Err((
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        json!({
+          "success": false,
+          "message": "Expected request with `Content-Type: application/json`",
+        })
+        .to_string(),
+      ));
+    }
+
+    let bytes = Bytes::from_request(req, state).await.map_err(|rejection| {
+      (
+        rejection.status(),
+        json!({"success": false, "message": rejection.body_text()}).to_string(),
+      )
+    })?;
+
+    let limits = Limits {
+      depth: 0,
+      max_depth: JsonMaxDepth::from_ref(state).0,
+      max_array_len: T::MAX_ARRAY_LEN,
+    };
+    let mut deserializer = serde_json::Deserializer::from_slice(&bytes);
+    let value = limits
+      .deserialize(&mut deserializer)
+      .and_then(|value| deserializer.end().map(|()| value))
+      .map_err(|e| {
+        let status = match e.classify() {
+          serde_json::error::Category::Data => StatusCode::UNPROCESSABLE_ENTITY,
+          _ => StatusCode::BAD_REQUEST,
+        };
+        (
+          status,
+          json!({"success": false, "message": e.to_string()}).to_string(),
+        )
+      })?;
+
+    let unknown: Vec<&str> = match &value {
+      Value::Object(fields) => fields
+        .keys()
+        .map(String::as_str)
+        .filter(|field| !T::FIELDS.contains(field))
+        .collect(),
+      _ => Vec::new(),
+    };
+
+    if !unknown.is_empty() && JsonMode::from_ref(state) == JsonMode::Strict {
+      return Err((
+        StatusCode::UNPROCESSABLE_ENTITY,
+        json!({
+          "success": false,
+          "message": format!("Unknown fields: {}", unknown.join(", ")),
+          "unknown_fields": unknown,
+        })
+        .to_string(),
+      ));
+    }
+
+    if !unknown.is_empty() {
+      feature_usage::used("unknown_fields");
+    }
+    let warnings = unknown
+      .iter()
+      .map(|field| format!("Unknown field `{field}` was ignored"))
+      .collect();
+
+    let body = serde_json::from_value(value).map_err(|e| {
+      (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        json!({"success": false, "message": e.to_string()}).to_string(),
+      )
+    })?;
+
+    Ok(JsonBody(body, warnings))
+  }
+}
+
+// Structs
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum JsonMode {
+  Strict,
+  Lenient,
+}
+
+impl JsonMode {
+  pub fn from_config(mode: &str) -> Self {
+    match mode {
+      "strict" => JsonMode::Strict,
+      "lenient" => JsonMode::Lenient,
+      other => panic!("Unknown JSON_MODE {other:?}, expected strict or lenient"),
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+pub struct JsonMaxDepth(pub usize);
+
+// Builds a Value like serde_json does, enforcing the limits on the way
+#[derive(Clone, Copy)]
+struct Limits {
+  depth: usize,
+  max_depth: usize,
+  max_array_len: usize,
+}
+
+impl Limits {
+  fn nested<E: de::Error>(self) -> Result<Self, E> {
+    if self.depth >= self.max_depth {
+      return Err(E::custom(format!(
+        "JSON is nested deeper than {} levels",
+        self.max_depth
+      )));
+    }
+    Ok(Limits {
+      depth: self.depth + 1,
+      ..self
+    })
+  }
+}
+
+impl<'de> DeserializeSeed<'de> for Limits {
+  type Value = Value;
+
+  fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+    deserializer.deserialize_any(self)
+  }
+}
+
+impl<'de> de::Visitor<'de> for Limits {
+  type Value = Value;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a JSON value")
+  }
+
+  fn visit_unit<E>(self) -> Result<Value, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+    Ok(Value::Bool(v))
+  }
+
+  fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+    Ok(Value::Number(v.into()))
+  }
+
+  fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+    Ok(Value::Number(v.into()))
+  }
+
+  fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+    Ok(Number::from_f64(v).map_or(Value::Null, Value::Number))
+  }
+
+  fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+    Ok(Value::String(v.to_owned()))
+  }
+
+  fn visit_string<E>(self, v: String) -> Result<Value, E> {
+    Ok(Value::String(v))
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+    let inner = self.nested()?;
+    let mut items = Vec::new();
+
+    while let Some(item) = seq.next_element_seed(inner)? {
+      if items.len() == self.max_array_len {
+        return Err(de::Error::custom(format!(
+          "JSON arrays may have at most {} items",
+          self.max_array_len
+        )));
+      }
+      items.push(item);
+    }
+
+    Ok(Value::Array(items))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+    let inner = self.nested()?;
+    let mut fields = Map::new();
+
+    while let Some(key) = map.next_key::<String>()? {
+      if fields.contains_key(&key) {
+        return Err(de::Error::custom(format!("duplicate key `{key}`")));
+      }
+      let value = map.next_value_seed(inner)?;
+      fields.insert(key, value);
+    }
+
+    Ok(Value::Object(fields))
+  }
+}
@@ -0,0 +1,130 @@
+// Optional feature usage
+//
+// Code that serves an optional feature calls `used` with its name, e.g.
+// `filter:priority`, `sort:name`, `locale`, `envelope:raw`,
+// `date_format:rfc3339`, `claim` or `unknown_fields`. Per route, every
+// request's features are counted, together with the number of requests,
+// and the totals are served at GET /admin/feature-usage. A feature no
+// client uses any more shows up there as a count that stops growing.
+//
+// FEATURE_USAGE_SAMPLE_RATE (default 0.01) of the requests that used any
+// optional feature are also logged as one JSON line
+// (`"event": "feature_usage"`) with the route and the features.
+//
+// Methods are labelled as in the metrics module, so extension methods share
+// `other` and cannot grow the report without bound.
+
+// Imports
+use axum::{
+  extract::{MatchedPath, Request, State},
+  http::StatusCode,
+  middleware::Next,
+  response::Response,
+};
+
+use serde::Serialize;
+use serde_json::json;
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  sync::{Arc, Mutex},
+};
+
+use crate::{metrics, response::Reply, AppState};
+
+// Constants
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+tokio::task_local! {
+  static FEATURES: Arc<Mutex<BTreeSet<String>>>;
+}
+
+// Store
+pub struct FeatureUsage {
+  sample_rate: f64,
+  routes: Mutex<BTreeMap<(String, String), RouteUsage>>,
+}
+
+impl FeatureUsage {
+  pub fn new(sample_rate: f64) -> Self {
+    Self {
+      sample_rate,
+      routes: Mutex::new(BTreeMap::new()),
+    }
+  }
+}
+
+// Functions
+
+// Marks an optional feature as used by the current request
+pub fn used(feature: impl Into<String>) {
+  let _ = FEATURES.try_with(|features| features.lock().unwrap().insert(feature.into()));
+}
+
+pub async fn track_features(State(state): State<AppState>, req: Request, next: Next) -> Response {
+  let method = metrics::method_label(req.method()).to_owned();
+  let route = req
+    .extensions()
+    .get::<MatchedPath>()
+    .map_or(UNMATCHED_ROUTE.to_owned(), |path| path.as_str().to_owned());
+
+  let features = Arc::new(Mutex::new(BTreeSet::new()));
+  let res = FEATURES.scope(features.clone(), next.run(req)).await;
+  let features = std::mem::take(&mut *features.lock().unwrap());
+
+  if !features.is_empty() && rand::random::<f64>() < state.feature_usage.sample_rate {
+    eprintln!(
+      "{}",
+      json!({
+        "event": "feature_usage",
+        "method": method,
+        "route": route,
+        "features": features,
+      })
+    );
+  }
+
+  let mut routes = state.feature_usage.routes.lock().unwrap();
+  let usage = routes.entry((method, route)).or_default();
+  usage.requests += 1;
+  for feature in features {
+    *usage.features.entry(feature).or_default() += 1;
+  }
+
+  res
+}
+
+pub async fn get_feature_usage(
+  State(state): State<AppState>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  let report: Vec<RouteReport> = state
+    .feature_usage
+    .routes
+    .lock()
+    .unwrap()
+    .iter()
+    .map(|((method, route), usage)| RouteReport {
+      method: method.clone(),
+      route: route.clone(),
+      usage: usage.clone(),
+    })
+    .collect();
+
+  Ok(reply.data(StatusCode::OK, report))
+}
+
+// Structs
+#[derive(Clone, Default, Serialize)]
+struct RouteUsage {
+  requests: u64,
+  features: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize)]
+struct RouteReport {
+  method: String,
+  route: String,
+  #[serde(flatten)]
+  usage: RouteUsage,
+}
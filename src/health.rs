@@ -0,0 +1,152 @@
+// Health history
+//
+// Every HEALTH_INTERVAL the `record_health` job takes a health check and
+// stores it in the health_history table:
+// - `db_latency_ms`: time to get a pooled connection and run `SELECT 1`
+// - `job_lag_ms`: how far past its schedule the most overdue background
+//   job is (paused jobs are left out)
+// - `requests` and `server_errors`: requests handled since the previous
+//   check and how many of them failed with a 5xx, with their ratio served
+//   as `error_rate`
+//
+// The table is a ring buffer of HEALTH_HISTORY_SIZE slots (default 1440, a
+// day of checks), so it keeps the latest checks without ever growing.
+// GET /admin/health/history returns them newest first, which shows when a
+// degradation started without external monitoring. A check that cannot
+// reach the database is not stored: the gap in the history marks the
+// outage, and the job's last error in /admin/jobs says why.
+
+// Imports
+use axum::{extract::State, http::StatusCode, response::Response};
+
+use serde::Serialize;
+
+use sqlx::PgPool;
+
+use std::{
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+use axum_crud_rest_types::timestamp::Timestamp;
+
+use crate::{db, jobs::Jobs, metrics::Metrics, response::Reply};
+
+// Constants
+pub const HEALTH_INTERVAL: Duration = Duration::from_secs(60);
+
+// Store
+pub struct HealthHistory {
+  size: i64,
+  // metrics totals at the last stored check
+  reported: Mutex<(u64, u64)>,
+}
+
+impl HealthHistory {
+  pub fn new(size: i64) -> Self {
+    Self {
+      size: size.max(1),
+      reported: Mutex::new((0, 0)),
+    }
+  }
+}
+
+// Functions
+pub async fn record_health(
+  pg_pool: &PgPool,
+  metrics: &Metrics,
+  jobs: &Jobs,
+  history: &HealthHistory,
+) -> Result<(), String> {
+  let started = Instant::now();
+  sqlx::query("SELECT 1")
+    .execute(pg_pool)
+    .await
+    .map_err(|e| e.to_string())?;
+  let db_latency = started.elapsed();
+
+  let job_lag = jobs.lag();
+  let (requests, server_errors) = metrics.totals();
+  let (reported_requests, reported_errors) = *history.reported.lock().unwrap();
+
+  // slots past a smaller HEALTH_HISTORY_SIZE are dropped on the next write
+  sqlx::query!(
+    "
+    WITH trimmed AS (DELETE FROM health_history WHERE slot >= $1)
+    INSERT INTO health_history (slot, checked_at, db_latency_ms, job_lag_ms, requests, server_errors)
+    VALUES (nextval('health_history_seq') % $1, now(), $2, $3, $4, $5)
+    ON CONFLICT (slot) DO UPDATE SET
+      checked_at = EXCLUDED.checked_at,
+      db_latency_ms = EXCLUDED.db_latency_ms,
+      job_lag_ms = EXCLUDED.job_lag_ms,
+      requests = EXCLUDED.requests,
+      server_errors = EXCLUDED.server_errors
+    ",
+    history.size,
+    db_latency.as_secs_f64() * 1000.0,
+    job_lag.as_millis() as i64,
+    (requests - reported_requests) as i64,
+    (server_errors - reported_errors) as i64
+  )
+  .execute(pg_pool)
+  .await
+  .map_err(|e| e.to_string())?;
+
+  *history.reported.lock().unwrap() = (requests, server_errors);
+  Ok(())
+}
+
+pub async fn get_health_history(
+  State(pg_pool): State<PgPool>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  let checks = history(&pg_pool).await.map_err(db::error_response)?;
+
+  Ok(reply.data(StatusCode::OK, checks))
+}
+
+// The stored checks, newest first
+pub async fn history(pg_pool: &PgPool) -> Result<Vec<HealthCheck>, sqlx::Error> {
+  let rows = db::retry_read(pg_pool, async |conn| {
+    sqlx::query!(
+      r#"
+      SELECT
+        (EXTRACT(EPOCH FROM checked_at) * 1000)::BIGINT AS "checked_at!",
+        db_latency_ms,
+        job_lag_ms,
+        requests,
+        server_errors
+      FROM health_history
+      ORDER BY checked_at DESC
+      "#
+    )
+    .fetch_all(conn)
+    .await
+  })
+  .await?;
+
+  Ok(
+    rows
+      .into_iter()
+      .map(|row| HealthCheck {
+        checked_at: Timestamp(row.checked_at),
+        db_latency_ms: row.db_latency_ms,
+        job_lag_ms: row.job_lag_ms,
+        requests: row.requests,
+        server_errors: row.server_errors,
+        error_rate: (row.requests > 0).then(|| row.server_errors as f64 / row.requests as f64),
+      })
+      .collect(),
+  )
+}
+
+// Structs
+#[derive(Serialize)]
+pub struct HealthCheck {
+  checked_at: Timestamp,
+  db_latency_ms: f64,
+  job_lag_ms: i64,
+  requests: i64,
+  server_errors: i64,
+  error_rate: Option<f64>,
+}
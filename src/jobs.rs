@@ -0,0 +1,222 @@
+// Background job registry
+//
+// Periodic work runs as named jobs registered with `Jobs::spawn`. Each job
+// runs on its interval and keeps its last run's start time, duration and
+// outcome. The admin API manages them:
+// - GET /admin/jobs lists jobs, their schedule and last run
+// - POST /admin/jobs/:name/run runs a job now, even when paused
+// - POST /admin/jobs/:name/pause and .../resume stop and restart the
+//   scheduled runs
+//
+// Jobs live in memory, so pausing lasts until the process restarts.
+
+// Imports
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  response::Response,
+};
+
+use serde::Serialize;
+use serde_json::json;
+
+use tokio::sync::Notify;
+
+use std::{
+  collections::BTreeMap,
+  future::Future,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use axum_crud_rest_types::timestamp::Timestamp;
+
+use crate::response::Reply;
+
+// Store
+pub struct Jobs {
+  jobs: Mutex<BTreeMap<&'static str, Arc<Job>>>,
+}
+
+impl Jobs {
+  pub fn new() -> Self {
+    Self {
+      jobs: Mutex::new(BTreeMap::new()),
+    }
+  }
+
+  pub fn spawn<F, Fut>(&self, name: &'static str, interval: Duration, run: F)
+  where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send,
+  {
+    let job = Arc::new(Job {
+      name,
+      interval,
+      spawned_at: Timestamp::now(),
+      state: Mutex::new(JobState::default()),
+      trigger: Notify::new(),
+    });
+    self.jobs.lock().unwrap().insert(name, job.clone());
+
+    tokio::spawn(async move {
+      let mut ticks = tokio::time::interval(job.interval);
+
+      loop {
+        tokio::select! {
+          _ = ticks.tick() => {
+            if job.state.lock().unwrap().paused {
+              continue;
+            }
+          }
+          _ = job.trigger.notified() => {}
+        }
+
+        job.state.lock().unwrap().running = true;
+        let started_at = Timestamp::now();
+        let started = Instant::now();
+        let result = run().await;
+
+        let mut state = job.state.lock().unwrap();
+        state.running = false;
+        state.last_run = Some(JobRun {
+          started_at,
+          duration_ms: started.elapsed().as_millis() as u64,
+          status: if result.is_ok() { "ok" } else { "error" },
+          error: result.err(),
+        });
+      }
+    });
+  }
+
+  // How far past its next scheduled run the most overdue unpaused job is
+  pub fn lag(&self) -> Duration {
+    let now = Timestamp::now().0;
+    self
+      .jobs
+      .lock()
+      .unwrap()
+      .values()
+      .filter_map(|job| {
+        let state = job.state.lock().unwrap();
+        if state.paused {
+          return None;
+        }
+        let last = state
+          .last_run
+          .as_ref()
+          .map_or(job.spawned_at, |run| run.started_at);
+        let late = now - last.0 - job.interval.as_millis() as i64;
+        Some(Duration::from_millis(late.max(0) as u64))
+      })
+      .max()
+      .unwrap_or_default()
+  }
+
+  pub fn reports(&self) -> Vec<JobReport> {
+    self
+      .jobs
+      .lock()
+      .unwrap()
+      .values()
+      .map(|job| job.report())
+      .collect()
+  }
+
+  fn get(&self, name: &str) -> Result<Arc<Job>, (StatusCode, String)> {
+    self.jobs.lock().unwrap().get(name).cloned().ok_or_else(|| {
+      (
+        StatusCode::NOT_FOUND,
+        json!({"success": false, "message": "Job not found"}).to_string(),
+      )
+    })
+  }
+}
+
+// Functions
+pub async fn list_jobs(
+  State(jobs): State<Arc<Jobs>>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  Ok(reply.data(StatusCode::OK, jobs.reports()))
+}
+
+pub async fn run_job(
+  State(jobs): State<Arc<Jobs>>,
+  Path(name): Path<String>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  let job = jobs.get(&name)?;
+  job.trigger.notify_one();
+
+  Ok(reply.data(StatusCode::ACCEPTED, job.report()))
+}
+
+pub async fn pause_job(
+  State(jobs): State<Arc<Jobs>>,
+  Path(name): Path<String>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  let job = jobs.get(&name)?;
+  job.state.lock().unwrap().paused = true;
+
+  Ok(reply.data(StatusCode::OK, job.report()))
+}
+
+pub async fn resume_job(
+  State(jobs): State<Arc<Jobs>>,
+  Path(name): Path<String>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  let job = jobs.get(&name)?;
+  job.state.lock().unwrap().paused = false;
+
+  Ok(reply.data(StatusCode::OK, job.report()))
+}
+
+// Structs
+struct Job {
+  name: &'static str,
+  interval: Duration,
+  spawned_at: Timestamp,
+  state: Mutex<JobState>,
+  trigger: Notify,
+}
+
+impl Job {
+  fn report(&self) -> JobReport {
+    let state = self.state.lock().unwrap();
+
+    JobReport {
+      name: self.name,
+      interval_secs: self.interval.as_secs(),
+      paused: state.paused,
+      running: state.running,
+      last_run: state.last_run.clone(),
+    }
+  }
+}
+
+#[derive(Default)]
+struct JobState {
+  paused: bool,
+  running: bool,
+  last_run: Option<JobRun>,
+}
+
+#[derive(Clone, Serialize)]
+struct JobRun {
+  started_at: Timestamp,
+  duration_ms: u64,
+  status: &'static str,
+  error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct JobReport {
+  name: &'static str,
+  interval_secs: u64,
+  paused: bool,
+  running: bool,
+  last_run: Option<JobRun>,
+}
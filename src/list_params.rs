@@ -0,0 +1,309 @@
+// Typed list parameters
+//
+// `ListParams<T>` reads the query string of a list endpoint and checks it
+// against the resource's `ListSchema`:
+// - `limit` (at least 1) and `offset` (at least 0) page through results
+// - `sort=<field>` or `sort=-<field>` (descending), for the fields the
+//   schema lists; the schema's unique tiebreak column always sorts last so
+//   pages are stable
+// - `<filter>=<value>` for the schema's filters, combined with AND
+//
+// Any other parameter is rejected with 422 unless the schema lists it in
+// `EXTRA` for the handler to read itself. The checked parameters become a
+// `ListQuery`: SQL built only from the schema's column names, with every
+// client value bound as a parameter. It either fetches all rows at once or
+// streams them through a bounded channel. `aggregate` runs another SELECT,
+// such as a MAX, over the same filtered rows.
+
+// Imports
+use axum::{
+  async_trait,
+  extract::{FromRequestParts, Query},
+  http::{request::Parts, StatusCode},
+};
+
+use serde_json::json;
+
+use futures_util::TryStreamExt;
+
+use sqlx::{
+  pool::PoolConnection,
+  postgres::{PgArguments, PgRow},
+  query::QueryAs,
+  FromRow, PgConnection, Postgres,
+};
+
+use tokio::sync::mpsc;
+
+use std::marker::PhantomData;
+
+use crate::{
+  diagnostics::{self, PlanParam},
+  feature_usage,
+};
+
+// Traits
+pub trait ListSchema {
+  // `SELECT <columns> FROM <table>`, without WHERE or ORDER BY
+  const SELECT: &'static str;
+  const SORTS: &'static [Sort];
+  const TIEBREAK: &'static str;
+  const FILTERS: &'static [Filter];
+  const EXTRA: &'static [&'static str];
+}
+
+// Extractor
+pub struct ListParams<T> {
+  pub limit: Option<i64>,
+  offset: i64,
+  sort: Option<(&'static Sort, bool)>,
+  filters: Vec<(&'static Filter, PlanParam)>,
+  schema: PhantomData<T>,
+}
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ListParams<T>
+where
+  T: ListSchema,
+  S: Send + Sync,
+{
+  type Rejection = (StatusCode, String);
+
+  async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    let Query(pairs) = Query::<Vec<(String, String)>>::from_request_parts(parts, state)
+      .await
+      .map_err(|rejection| invalid(rejection.body_text()))?;
+
+    let mut params = ListParams {
+      limit: None,
+      offset: 0,
+      sort: None,
+      filters: Vec::new(),
+      schema: PhantomData,
+    };
+
+    for (key, value) in pairs {
+      match key.as_str() {
+        "limit" => {
+          let limit = value.parse().ok().filter(|limit| *limit >= 1);
+          params.limit = Some(limit.ok_or_else(|| invalid("`limit` must be at least 1"))?);
+          feature_usage::used("limit");
+        }
+        "offset" => {
+          let offset = value.parse().ok().filter(|offset| *offset >= 0);
+          params.offset = offset.ok_or_else(|| invalid("`offset` must be at least 0"))?;
+          feature_usage::used("offset");
+        }
+        "sort" => {
+          let (name, descending) = match value.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (value.as_str(), false),
+          };
+          let sort = T::SORTS
+            .iter()
+            .find(|sort| sort.name == name)
+            .ok_or_else(|| invalid(format!("Cannot sort by `{name}`")))?;
+          params.sort = Some((sort, descending));
+          feature_usage::used(format!("sort:{}", sort.name));
+        }
+        key if T::EXTRA.contains(&key) => {}
+        key => {
+          let filter = T::FILTERS
+            .iter()
+            .find(|filter| filter.name == key)
+            .ok_or_else(|| invalid(format!("Unknown query parameter `{key}`")))?;
+          let value = filter
+            .kind
+            .parse(value)
+            .ok_or_else(|| invalid(format!("`{key}` must be an integer")))?;
+          params.filters.push((filter, value));
+          feature_usage::used(format!("filter:{}", filter.name));
+        }
+      }
+    }
+
+    Ok(params)
+  }
+}
+
+impl<T: ListSchema> ListParams<T> {
+  // Sort field used when the client did not pick one
+  pub fn with_default_sort(mut self, name: &str) -> Self {
+    if self.sort.is_none() {
+      self.sort = T::SORTS
+        .iter()
+        .find(|sort| sort.name == name)
+        .map(|sort| (sort, false));
+    }
+    self
+  }
+
+  // The query for at most `limit` rows, with text sorts in `collation`
+  pub fn query(&self, limit: i64, collation: Option<&str>) -> ListQuery {
+    let ListQuery {
+      mut sql,
+      mut params,
+    } = self.aggregate(T::SELECT);
+
+    sql.push_str(" ORDER BY ");
+    if let Some((sort, descending)) = self.sort {
+      sql.push_str(sort.column);
+      if let Some(collation) = collation.filter(|_| sort.text) {
+        sql.push_str(&format!(" COLLATE \"{}\"", collation.replace('"', "\"\"")));
+      }
+      if descending {
+        sql.push_str(" DESC");
+      }
+      sql.push_str(", ");
+    }
+    sql.push_str(T::TIEBREAK);
+
+    params.push(PlanParam::BigInt(Some(limit)));
+    params.push(PlanParam::BigInt(Some(self.offset)));
+    sql.push_str(&format!(
+      " LIMIT ${} OFFSET ${}",
+      params.len() - 1,
+      params.len()
+    ));
+
+    ListQuery { sql, params }
+  }
+
+  // `select` (`SELECT <expressions> FROM <table>`) over the rows the
+  // filters match, without ORDER BY or LIMIT
+  pub fn aggregate(&self, select: &str) -> ListQuery {
+    let mut sql = String::from(select);
+    let mut params = Vec::new();
+
+    for (i, (filter, value)) in self.filters.iter().enumerate() {
+      params.push(value.clone());
+      let placeholder = params.len();
+      let keyword = if i == 0 { "WHERE" } else { "AND" };
+      let condition = match filter.kind {
+        FilterKind::Int | FilterKind::BigInt | FilterKind::Text => {
+          format!("{} = ${placeholder}", filter.column)
+        }
+        FilterKind::Contains => format!("{} ILIKE '%' || ${placeholder} || '%'", filter.column),
+      };
+      sql.push_str(&format!(" {keyword} {condition}"));
+    }
+
+    ListQuery { sql, params }
+  }
+}
+
+fn invalid(message: impl Into<String>) -> (StatusCode, String) {
+  (
+    StatusCode::UNPROCESSABLE_ENTITY,
+    json!({"success": false, "message": message.into()}).to_string(),
+  )
+}
+
+// Structs
+pub struct Sort {
+  pub name: &'static str,
+  pub column: &'static str,
+  // text columns follow the requested collation
+  pub text: bool,
+}
+
+pub struct Filter {
+  pub name: &'static str,
+  pub column: &'static str,
+  pub kind: FilterKind,
+}
+
+pub enum FilterKind {
+  // INTEGER columns
+  Int,
+  // BIGINT columns, such as generated ids
+  BigInt,
+  Text,
+  // case-insensitive substring match
+  Contains,
+}
+
+impl FilterKind {
+  fn parse(&self, value: String) -> Option<PlanParam> {
+    match self {
+      FilterKind::Int => value.parse().ok().map(|v| PlanParam::Int(Some(v))),
+      FilterKind::BigInt => value.parse().ok().map(|v| PlanParam::BigInt(Some(v))),
+      FilterKind::Text => Some(PlanParam::Text(Some(value))),
+      FilterKind::Contains => {
+        let escaped = value
+          .replace('\\', "\\\\")
+          .replace('%', "\\%")
+          .replace('_', "\\_");
+        Some(PlanParam::Text(Some(escaped)))
+      }
+    }
+  }
+}
+
+pub struct ListQuery {
+  sql: String,
+  params: Vec<PlanParam>,
+}
+
+impl ListQuery {
+  pub async fn fetch_all<R>(&self, conn: &mut PgConnection) -> Result<Vec<R>, sqlx::Error>
+  where
+    R: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+  {
+    diagnostics::record(self.sql.clone(), self.params.clone());
+    self.bind().fetch_all(conn).await
+  }
+
+  pub async fn fetch_one<R>(&self, conn: &mut PgConnection) -> Result<R, sqlx::Error>
+  where
+    R: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+  {
+    diagnostics::record(self.sql.clone(), self.params.clone());
+    self.bind().fetch_one(conn).await
+  }
+
+  // Sends the rows one by one, at most `buffer` ahead of the receiver, so a
+  // slow reader pauses the query instead of rows piling up in memory. The
+  // query stops when the receiver is dropped.
+  pub fn stream<R>(
+    self,
+    mut conn: PoolConnection<Postgres>,
+    buffer: usize,
+  ) -> mpsc::Receiver<Result<R, sqlx::Error>>
+  where
+    R: for<'r> FromRow<'r, PgRow> + Send + Unpin + 'static,
+  {
+    diagnostics::record(self.sql.clone(), self.params.clone());
+
+    let (tx, rx) = mpsc::channel(buffer);
+    tokio::spawn(async move {
+      let mut rows = self.bind::<R>().fetch(&mut *conn);
+      while let Some(row) = rows.try_next().await.transpose() {
+        let failed = row.is_err();
+        if tx.send(row).await.is_err() || failed {
+          break;
+        }
+      }
+    });
+
+    rx
+  }
+
+  fn bind<R>(&self) -> QueryAs<'_, Postgres, R, PgArguments>
+  where
+    R: for<'r> FromRow<'r, PgRow>,
+  {
+    let mut query = sqlx::query_as::<Postgres, R>(&self.sql);
+    for param in &self.params {
+      query = match param {
+        PlanParam::Int(v) => query.bind(*v),
+        PlanParam::BigInt(v) => query.bind(*v),
+        PlanParam::Text(v) => query.bind(v.clone()),
+        PlanParam::IntArray(v) => query.bind(v.clone()),
+        PlanParam::BigIntArray(v) => query.bind(v.clone()),
+        PlanParam::TextArray(v) => query.bind(v.clone()),
+      };
+    }
+    query
+  }
+}
@@ -1,40 +1,247 @@
 // From: Build a CRUD REST API with Rust Axum | Tutorial
 // https://www.youtube.com/watch?v=NJsTgmayHZY
 
+// Modules
+mod admin;
+mod db;
+mod deadline;
+mod diagnostics;
+mod duplicates;
+mod envelope_usage;
+mod explorer;
+mod exports;
+mod extract;
+mod feature_usage;
+mod health;
+mod ids;
+mod jobs;
+mod list_params;
+mod metrics;
+mod migration_lint;
+mod operations;
+mod queue;
+mod quick_add;
+mod registration;
+mod reprioritize;
+mod response;
+mod schema_drift;
+mod self_check;
+mod support_bundle;
+mod task_stats;
+mod timing;
+mod tombstones;
+
 // Imports
 use axum::{
-  extract::{Path, State},
-  http::StatusCode,
-  routing::{get, patch},
-  Json, Router,
+  body::{Body, Bytes},
+  extract::{FromRef, Path, Query, State},
+  http::{header, StatusCode},
+  middleware,
+  response::{IntoResponse, Response},
+  routing::{get, patch, post},
+  Router,
 };
 
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use serde_json::json;
 
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use sqlx::{migrate::Migrator, postgres::PgPoolOptions, Connection, PgConnection, PgPool};
+
+use tokio::{net::TcpListener, sync::Semaphore};
+
+use std::{
+  net::SocketAddr,
+  sync::Arc,
+  time::{Duration, UNIX_EPOCH},
+};
+
+use axum_crud_rest_types::{
+  routes, validation, CreateTaskReq, CreateTaskRow, TaskRow, UpdateTaskReq, UpsertTaskReq,
+};
 
-use tokio::net::TcpListener;
+use diagnostics::{traced_query, traced_query_as, traced_query_scalar, Diagnostics};
+use duplicates::Duplicates;
+use envelope_usage::EnvelopeUsage;
+use exports::Exports;
+use extract::{JsonBody, JsonMaxDepth, JsonMode, KnownFields};
+use feature_usage::FeatureUsage;
+use health::HealthHistory;
+use ids::IdGenerator;
+use jobs::Jobs;
+use list_params::{Filter, FilterKind, ListParams, ListSchema, Sort};
+use metrics::Metrics;
+use operations::Operations;
+use queue::LeaseTtl;
+use registration::Registration;
+use response::Reply;
+use task_stats::StatsCache;
 
 // Aliases
 use std::env::var as envar;
 
+// Constants
+const DB_MAX_CONNECTIONS: u32 = 16;
+// rows a task stream reads ahead of a slow client
+const STREAM_BUFFER: usize = 64;
+// the migrations embedded in this build
+static MIGRATOR: Migrator = sqlx::migrate!();
+
 #[tokio::main]
 async fn main() {
   // expose the environment variables
   dotenvy::dotenv().expect("Unable to access .env file");
 
+  // `--check`, `migrate --check` and `schema --dump` report on the setup
+  // instead of serving
+  let command: Vec<String> = std::env::args().skip(1).collect();
+  let migrator = &MIGRATOR;
+
+  if command == ["schema", "--dump"] {
+    let database_url = envar("DATABASE_URL").expect("DATABASE_URL not found in the env file");
+    let mut conn = PgConnection::connect(&database_url)
+      .await
+      .expect("Can't connect to database");
+    for line in schema_drift::dump(&mut conn)
+      .await
+      .expect("Can't read the database schema")
+    {
+      println!("{line}");
+    }
+    return;
+  }
+
+  // check the environment before relying on it
+  let self_check = self_check::run(migrator).await;
+  self_check.print();
+  if command == ["--check"] {
+    std::process::exit(if self_check.passed() { 0 } else { 1 });
+  }
+
   // set variables from the environment variables
   let server_address = envar("SERVER_ADDRESS").unwrap_or("127.0.0.1:3000".to_owned());
   let database_url = envar("DATABASE_URL").expect("DATABASE_URL not found in the env file");
+  let db_min_connections = envar("DB_MIN_CONNECTIONS")
+    .ok()
+    .and_then(|n| n.parse().ok())
+    .unwrap_or(0);
+  let db_warm_up = envar("DB_WARM_UP").is_ok_and(|v| v == "true" || v == "1");
+  if db_warm_up && db_min_connections == 0 {
+    panic!("DB_WARM_UP opens DB_MIN_CONNECTIONS connections, set it above 0");
+  }
+  let db_test_before_acquire =
+    envar("DB_TEST_BEFORE_ACQUIRE").map_or(true, |v| v != "false" && v != "0");
+  let export_signing_key = envar("EXPORT_SIGNING_KEY")
+    .map(String::into_bytes)
+    .unwrap_or_else(|_| rand::random::<[u8; 32]>().to_vec());
+  let export_url_ttl = envar("EXPORT_URL_TTL_SECS")
+    .ok()
+    .and_then(|secs| secs.parse().ok())
+    .unwrap_or(900);
+  let export_retention = envar("EXPORT_RETENTION_SECS")
+    .ok()
+    .and_then(|secs| secs.parse().ok())
+    .unwrap_or(3600);
+  let operation_retention = envar("OPERATION_RETENTION_SECS")
+    .ok()
+    .and_then(|secs| secs.parse().ok())
+    .unwrap_or(3600);
+  let id_strategy = envar("ID_STRATEGY").unwrap_or("serial".to_owned());
+  let id_node_id = envar("ID_NODE_ID").ok();
+  let json_mode = JsonMode::from_config(&envar("JSON_MODE").unwrap_or("lenient".to_owned()));
+  let json_max_depth = envar("JSON_MAX_DEPTH")
+    .ok()
+    .and_then(|depth| depth.parse().ok())
+    .unwrap_or(32);
+  let admin_token = envar("ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+  let slow_request_threshold = envar("SLOW_REQUEST_MS")
+    .ok()
+    .and_then(|ms| ms.parse().ok())
+    .map(Duration::from_millis);
+  let feature_usage_sample_rate = envar("FEATURE_USAGE_SAMPLE_RATE")
+    .ok()
+    .and_then(|rate| rate.parse().ok())
+    .unwrap_or(0.01);
+  let slow_request_sample_rate = envar("SLOW_REQUEST_SAMPLE_RATE")
+    .ok()
+    .and_then(|rate| rate.parse().ok())
+    .unwrap_or(0.01);
+  let list_row_cap = envar("LIST_ROW_CAP")
+    .ok()
+    .and_then(|cap| cap.parse().ok())
+    .unwrap_or(10_000);
+  let task_lease_secs = envar("TASK_LEASE_SECS")
+    .ok()
+    .and_then(|secs| secs.parse().ok())
+    .unwrap_or(300);
+  let task_stats_ttl = envar("TASK_STATS_TTL_SECS")
+    .ok()
+    .and_then(|secs| secs.parse().ok())
+    .unwrap_or(5);
+  // each open stream holds a connection, the rest stay for other requests
+  let max_streams = envar("MAX_TASK_STREAMS")
+    .ok()
+    .and_then(|n| n.parse().ok())
+    .unwrap_or(4);
+  if max_streams >= DB_MAX_CONNECTIONS as usize {
+    panic!("MAX_TASK_STREAMS must be below the pool's {DB_MAX_CONNECTIONS} connections");
+  }
+  let server_timing = envar("SERVER_TIMING").is_ok_and(|v| v == "true" || v == "1");
+  let duplicate_windows = envar("DUPLICATE_WINDOWS").unwrap_or_default();
+  let health_history_size = envar("HEALTH_HISTORY_SIZE")
+    .ok()
+    .and_then(|size| size.parse().ok())
+    .unwrap_or(1440);
+  let migration_mode = envar("MIGRATION_MODE").unwrap_or("plain".to_owned());
+  let consul_http_addr = envar("CONSUL_HTTP_ADDR").ok();
+  let service_name = envar("SERVICE_NAME").unwrap_or("axum_crud_rest".to_owned());
+  let service_advertise_address = envar("SERVICE_ADVERTISE_ADDRESS").ok();
 
   // create the database pool
   let db_pool = PgPoolOptions::new()
-    .max_connections(16)
+    .max_connections(DB_MAX_CONNECTIONS)
+    .min_connections(db_min_connections)
+    .test_before_acquire(db_test_before_acquire)
     .connect(&database_url)
     .await
     .expect("Can't connect to database");
 
+  if command == ["migrate", "--check"] {
+    let clean = migration_lint::check(&db_pool, migrator)
+      .await
+      .expect("Can't read applied migrations");
+    std::process::exit(if clean { 0 } else { 1 });
+  }
+
+  // bring the schema up to date
+  match migration_mode.as_str() {
+    "plain" => migrator
+      .run(&db_pool)
+      .await
+      .expect("Can't run database migrations"),
+    "safe" => migration_lint::run_safe(&db_pool, migrator)
+      .await
+      .unwrap_or_else(|e| panic!("Can't run database migrations: {e}")),
+    other => panic!("Unknown MIGRATION_MODE {other:?}, expected plain or safe"),
+  }
+
+  // refuse to serve on a schema the migrations did not produce
+  let drift = schema_drift::check(&mut db_pool.acquire().await.expect("Can't connect to database"))
+    .await
+    .expect("Can't read the database schema");
+  if !drift.is_empty() {
+    for line in &drift {
+      eprintln!("{line}");
+    }
+    panic!("Database schema differs from schema.txt, see the lines above");
+  }
+
+  // open the minimum connections up front so the first requests don't pay for them
+  if db_warm_up {
+    db::warm_up(&db_pool, db_min_connections)
+      .await
+      .expect("Can't warm up the database pool");
+  }
+
   // create our TCP listener
   let listener = TcpListener::bind(server_address)
     .await
@@ -42,130 +249,671 @@ async fn main() {
 
   println!("Listening on {}", listener.local_addr().unwrap());
 
+  // announce ourselves to the service registry
+  let registration = match consul_http_addr {
+    Some(consul_addr) => {
+      let advertise_addr = service_advertise_address
+        .map(|addr| {
+          addr
+            .parse()
+            .expect("SERVICE_ADVERTISE_ADDRESS is not host:port")
+        })
+        .unwrap_or_else(|| listener.local_addr().unwrap());
+
+      Some(
+        Registration::register(consul_addr, service_name, advertise_addr, db_pool.clone())
+          .await
+          .expect("Could not register with Consul"),
+      )
+    }
+    None => None,
+  };
+
+  // build the shared state
+  let state = AppState {
+    db_pool,
+    exports: Arc::new(Exports::new(
+      export_signing_key,
+      Duration::from_secs(export_url_ttl),
+      Duration::from_secs(export_retention),
+    )),
+    operations: Arc::new(Operations::new(Duration::from_secs(operation_retention))),
+    ids: ids::from_config(&id_strategy, id_node_id.as_deref()),
+    json_mode,
+    json_max_depth: JsonMaxDepth(json_max_depth),
+    diagnostics: Arc::new(Diagnostics::new(
+      slow_request_threshold,
+      slow_request_sample_rate,
+    )),
+    admin_token: admin_token.map(Arc::from),
+    metrics: Arc::new(Metrics::new()),
+    envelope_usage: Arc::new(EnvelopeUsage::new()),
+    server_timing,
+    row_cap: RowCap(list_row_cap),
+    lease_ttl: LeaseTtl(Duration::from_secs(task_lease_secs)),
+    jobs: Arc::new(Jobs::new()),
+    duplicates: Arc::new(Duplicates::from_config(&duplicate_windows)),
+    feature_usage: Arc::new(FeatureUsage::new(feature_usage_sample_rate)),
+    health_history: Arc::new(HealthHistory::new(health_history_size)),
+    task_stats: Arc::new(StatsCache::new(Duration::from_secs(task_stats_ttl))),
+    stream_slots: StreamSlots(Arc::new(Semaphore::new(max_streams))),
+  };
+
+  // start the background jobs
+  let (pg_pool, metrics) = (state.db_pool.clone(), state.metrics.clone());
+  state
+    .jobs
+    .spawn("release_expired_leases", queue::SWEEP_INTERVAL, move || {
+      let (pg_pool, metrics) = (pg_pool.clone(), metrics.clone());
+      async move { queue::release_expired_leases(&pg_pool, &metrics).await }
+    });
+  let exports = state.exports.clone();
+  state
+    .jobs
+    .spawn("expire_exports", exports::EXPIRE_INTERVAL, move || {
+      let exports = exports.clone();
+      async move {
+        exports.expire();
+        Ok(())
+      }
+    });
+  let operations = state.operations.clone();
+  state.jobs.spawn(
+    "expire_operations",
+    operations::EXPIRE_INTERVAL,
+    move || {
+      let operations = operations.clone();
+      async move {
+        operations.expire();
+        Ok(())
+      }
+    },
+  );
+  let (pg_pool, metrics) = (state.db_pool.clone(), state.metrics.clone());
+  state
+    .jobs
+    .spawn("aggregate_task_stats", metrics::STATS_INTERVAL, move || {
+      let (pg_pool, metrics) = (pg_pool.clone(), metrics.clone());
+      async move {
+        metrics::aggregate_task_stats(&pg_pool, &metrics)
+          .await
+          .map(|_| ())
+          .map_err(|e| e.to_string())
+      }
+    });
+  let pg_pool = state.db_pool.clone();
+  state
+    .jobs
+    .spawn("prune_tombstones", tombstones::PRUNE_INTERVAL, move || {
+      let pg_pool = pg_pool.clone();
+      async move { tombstones::prune_tombstones(&pg_pool).await }
+    });
+  let (pg_pool, metrics, jobs, health_history) = (
+    state.db_pool.clone(),
+    state.metrics.clone(),
+    state.jobs.clone(),
+    state.health_history.clone(),
+  );
+  state
+    .jobs
+    .spawn("record_health", health::HEALTH_INTERVAL, move || {
+      let (pg_pool, metrics, jobs, health_history) = (
+        pg_pool.clone(),
+        metrics.clone(),
+        jobs.clone(),
+        health_history.clone(),
+      );
+      async move { health::record_health(&pg_pool, &metrics, &jobs, &health_history).await }
+    });
+
   // compose the routes
+  let admin = Router::new()
+    .route(
+      routes::admin::QUERY_PLANS,
+      get(diagnostics::get_query_plans),
+    )
+    .route(
+      routes::admin::ENVELOPE_USAGE,
+      get(envelope_usage::get_envelope_usage),
+    )
+    .route(
+      routes::admin::FEATURE_USAGE,
+      get(feature_usage::get_feature_usage),
+    )
+    .route(routes::admin::JOBS, get(jobs::list_jobs))
+    .route(routes::admin::JOB_RUN, post(jobs::run_job))
+    .route(routes::admin::JOB_PAUSE, post(jobs::pause_job))
+    .route(routes::admin::JOB_RESUME, post(jobs::resume_job))
+    .route(
+      routes::admin::HEALTH_HISTORY,
+      get(health::get_health_history),
+    )
+    .route(
+      routes::admin::SUPPORT_BUNDLE,
+      get(support_bundle::get_support_bundle),
+    )
+    .route(routes::admin::TABLES, get(explorer::list_tables))
+    .route(routes::admin::TABLE, get(explorer::get_table))
+    .route_layer(middleware::from_fn_with_state(
+      state.clone(),
+      admin::require_admin,
+    ));
+
   let app = Router::new()
-    .route("/", get(|| async { "Hello World" }))
-    .route("/tasks", get(get_tasks).post(create_task))
-    .route("/tasks/:task_id", patch(update_task).delete(delete_task))
-    .with_state(db_pool);
+    .route(routes::ROOT, get(|| async { "Hello World" }))
+    .route(
+      routes::TASKS,
+      get(get_tasks).post(create_task).put(upsert_task),
+    )
+    .route(routes::TASKS_QUICK, post(quick_add::quick_add_task))
+    .route(
+      routes::TASKS_REPRIORITIZE,
+      post(reprioritize::reprioritize_tasks),
+    )
+    .route(routes::TASKS_NEXT, get(queue::next_task))
+    .route(routes::TASKS_STREAM, get(stream_tasks))
+    .route(routes::TASKS_STATS, get(task_stats::get_task_stats))
+    .route(routes::TASK_COMPLETE, post(queue::complete_task))
+    .route(routes::TASK_LEASE_RENEW, post(queue::renew_lease))
+    .route(routes::TASK, patch(update_task).delete(delete_task))
+    .route(routes::EXPORTS, post(exports::create_export))
+    .route(routes::EXPORT_DOWNLOAD, get(exports::download_export))
+    .route(routes::OPERATION, get(operations::get_operation))
+    .route(routes::OPERATION_CANCEL, post(operations::cancel_operation))
+    .route(routes::METRICS, get(metrics::get_metrics))
+    .nest(routes::ADMIN, admin)
+    .layer(middleware::from_fn_with_state(
+      state.clone(),
+      duplicates::detect,
+    ))
+    .layer(middleware::from_fn_with_state(
+      state.clone(),
+      feature_usage::track_features,
+    ))
+    .layer(middleware::from_fn_with_state(
+      state.clone(),
+      metrics::track_requests,
+    ))
+    .layer(middleware::from_fn_with_state(
+      state.clone(),
+      diagnostics::capture_slow_requests,
+    ))
+    .layer(middleware::from_fn(deadline::propagate_deadline))
+    .layer(middleware::from_fn_with_state(
+      state.clone(),
+      timing::server_timing,
+    ))
+    .with_state(state);
 
-  // serve the application
-  axum::serve(listener, app)
-    .await
-    .expect("Error serving application");
+  // serve the application; leave the registry before draining, so no new
+  // traffic is sent while in-flight requests finish
+  axum::serve(
+    listener,
+    app.into_make_service_with_connect_info::<SocketAddr>(),
+  )
+  .with_graceful_shutdown(async move {
+    shutdown_signal().await;
+    if let Some(registration) = registration {
+      registration.deregister().await;
+    }
+  })
+  .await
+  .expect("Error serving application");
+}
+
+async fn shutdown_signal() {
+  let ctrl_c = tokio::signal::ctrl_c();
+
+  #[cfg(unix)]
+  let terminate = async {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+      .expect("Could not install the SIGTERM handler")
+      .recv()
+      .await;
+  };
+  #[cfg(not(unix))]
+  let terminate = std::future::pending::<()>();
+
+  tokio::select! {
+    _ = ctrl_c => {},
+    _ = terminate => {},
+  }
 }
 
 // Functions
 async fn get_tasks(
   State(pg_pool): State<PgPool>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-  let rows = sqlx::query_as!(TaskRow, "SELECT * FROM tasks ORDER BY task_id")
-    .fetch_all(&pg_pool)
+  State(row_cap): State<RowCap>,
+  Query(params): Query<ListTasksParams>,
+  list: ListParams<TaskRow>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  // the filters apply to the tasks and to the tombstones of rows that left
+  // the list, see the tombstones module
+  let query = list.aggregate(
+    "
+    SELECT EXTRACT(EPOCH FROM GREATEST(
+      MAX(changed_at),
+      (SELECT pruned_until FROM task_list_changes)
+    ))::BIGINT
+    FROM (
+      SELECT name, priority, external_id, updated_at AS changed_at FROM tasks
+      UNION ALL
+      SELECT name, priority, external_id, removed_at FROM task_list_tombstones
+    ) AS list
+    ",
+  );
+  let (last_modified,): (Option<i64>,) =
+    db::retry_read(&pg_pool, async move |conn| query.fetch_one(conn).await)
+      .await
+      .map_err(db::error_response)?;
+  let last_modified = last_modified.map(|secs| UNIX_EPOCH + Duration::from_secs(secs as u64));
+
+  let reply = reply.last_modified(last_modified);
+  if let Some(res) = reply.not_modified() {
+    return Ok(res);
+  }
+
+  let (list, collation) = sort_by_locale(&pg_pool, list, params.locale).await?;
+
+  let requested_limit = list.limit;
+  if requested_limit.is_some_and(|limit| limit > row_cap.0) {
+    return Err((
+      StatusCode::UNPROCESSABLE_ENTITY,
+      json!({"success": false, "message": format!("`limit` can be at most {}", row_cap.0)})
+        .to_string(),
+    ));
+  }
+
+  // without a limit, one row past the cap tells us the list is too large without counting it
+  let query = list.query(
+    requested_limit.unwrap_or(row_cap.0 + 1),
+    collation.as_deref(),
+  );
+  let rows: Vec<TaskRow> = db::retry_read(&pg_pool, async move |conn| query.fetch_all(conn).await)
     .await
-    .map_err(|e| {
-      (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        json!({"success": false, "message": e.to_string()}).to_string(),
-      )
-    })?;
+    .map_err(db::error_response)?;
 
-  Ok((
-    StatusCode::OK,
-    json!({ "success": true, "data": rows }).to_string(),
-  ))
+  if requested_limit.is_none() && rows.len() as i64 > row_cap.0 {
+    return Err((
+      StatusCode::UNPROCESSABLE_ENTITY,
+      json!({
+        "success": false,
+        "code": "too_many_rows",
+        "message": format!(
+          "The task list has more than {} rows, page through it with `limit` and `offset` or use POST /exports to download all tasks",
+          row_cap.0
+        ),
+      })
+      .to_string(),
+    ));
+  }
+
+  Ok(reply.data(StatusCode::OK, rows))
 }
 
-async fn create_task(
+// GET /tasks/stream sends every matching task as one JSON line, without the
+// row cap of GET /tasks. Rows are read from the database only as fast as the
+// client takes them; each open stream holds a database connection, so at
+// most MAX_TASK_STREAMS (default 4) are open at once and further ones get a
+// 503.
+async fn stream_tasks(
   State(pg_pool): State<PgPool>,
-  Json(task): Json<CreateTaskReq>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-  let row = sqlx::query_as!(
-    CreateTaskRow,
-    "INSERT INTO tasks (name, priority) VALUES ($1, $2) RETURNING task_id",
-    task.name,
-    task.priority
+  State(StreamSlots(slots)): State<StreamSlots>,
+  Query(params): Query<ListTasksParams>,
+  list: ListParams<TaskRow>,
+) -> Result<Response, (StatusCode, String)> {
+  let slot = slots.try_acquire_owned().map_err(|_| {
+    (
+      StatusCode::SERVICE_UNAVAILABLE,
+      json!({"success": false, "message": "Too many task streams are open, try again later"})
+        .to_string(),
+    )
+  })?;
+
+  let (list, collation) = sort_by_locale(&pg_pool, list, params.locale).await?;
+
+  let conn = timing::db(pg_pool.acquire())
+    .await
+    .map_err(db::error_response)?;
+  let rows = list
+    .query(list.limit.unwrap_or(i64::MAX), collation.as_deref())
+    .stream::<TaskRow>(conn, STREAM_BUFFER);
+
+  // a failed query ends the body early, so the client sees the stream break;
+  // the slot is freed when the body is dropped
+  let lines = futures_util::stream::unfold((rows, slot), |(mut rows, slot)| async move {
+    let line = rows.recv().await?.map(|row| {
+      let mut line = serde_json::to_vec(&row).unwrap();
+      line.push(b'\n');
+      Bytes::from(line)
+    });
+    Some((line, (rows, slot)))
+  });
+
+  Ok(
+    (
+      [(header::CONTENT_TYPE, "application/x-ndjson")],
+      Body::from_stream(lines),
+    )
+      .into_response(),
   )
-  .fetch_one(&pg_pool)
+}
+
+// A locale sorts by name in that locale's collation, if Postgres has it
+async fn sort_by_locale(
+  pg_pool: &PgPool,
+  list: ListParams<TaskRow>,
+  locale: Option<String>,
+) -> Result<(ListParams<TaskRow>, Option<String>), (StatusCode, String)> {
+  let Some(locale) = locale else {
+    return Ok((list, None));
+  };
+  feature_usage::used("locale");
+
+  // only collations Postgres actually has can end up in the query
+  let collation = traced_query_scalar!(
+    "SELECT collname AS \"collname!\" FROM pg_collation WHERE collprovider = 'i' AND collname = $1",
+    format!("{locale}-x-icu")
+  )
+  .fetch_optional(pg_pool)
   .await
-  .map_err(|e| {
+  .map_err(db::error_response)?
+  .ok_or_else(|| {
     (
-      StatusCode::INTERNAL_SERVER_ERROR,
-      json!({"success": false, "message": e.to_string()}).to_string(),
+      StatusCode::UNPROCESSABLE_ENTITY,
+      json!({"success": false, "message": format!("Unsupported locale `{locale}`")}).to_string(),
     )
   })?;
 
-  Ok((
-    StatusCode::CREATED,
-    json!({"success": true, "data": row}).to_string(),
+  Ok((list.with_default_sort("name"), Some(collation)))
+}
+
+async fn create_task(
+  State(pg_pool): State<PgPool>,
+  State(ids): State<Arc<dyn IdGenerator>>,
+  reply: Reply,
+  JsonBody(task, mut warnings): JsonBody<CreateTaskReq>,
+) -> Result<Response, (StatusCode, String)> {
+  let name = validation::name(task.name, &mut warnings);
+  let priority = validation::priority(task.priority, &mut warnings);
+
+  let task_id = ids.next_id()?;
+  let mut tx = db::begin(&pg_pool).await.map_err(db::error_response)?;
+  let row = timing::db(
+    traced_query_as!(
+      CreateTaskRow,
+      "
+      INSERT INTO tasks (task_id, name, priority)
+      VALUES (COALESCE($1, nextval('tasks_task_id_seq')), $2, $3)
+      RETURNING task_id
+      ",
+      task_id,
+      name,
+      priority
+    )
+    .fetch_one(&mut *tx),
+  )
+  .await
+  .map_err(db::error_response)?;
+  db::commit(tx).await.map_err(db::error_response)?;
+
+  Ok(reply.warn(warnings).data(StatusCode::CREATED, row))
+}
+
+// PUT /tasks creates or updates the task with the given external_id
+async fn upsert_task(
+  State(pg_pool): State<PgPool>,
+  State(ids): State<Arc<dyn IdGenerator>>,
+  reply: Reply,
+  JsonBody(task, mut warnings): JsonBody<UpsertTaskReq>,
+) -> Result<Response, (StatusCode, String)> {
+  if task.external_id.is_empty() {
+    return Err((
+      StatusCode::UNPROCESSABLE_ENTITY,
+      json!({"success": false, "message": "external_id must not be empty"}).to_string(),
+    ));
+  }
+
+  let name = validation::name(task.name, &mut warnings);
+  let priority = validation::priority(task.priority, &mut warnings);
+
+  let task_id = ids.next_id()?;
+  let mut tx = db::begin(&pg_pool).await.map_err(db::error_response)?;
+  let row = timing::db(
+    traced_query!(
+      r#"
+      INSERT INTO tasks (task_id, external_id, name, priority)
+      VALUES (COALESCE($1, nextval('tasks_task_id_seq')), $2, $3, $4)
+      ON CONFLICT (external_id) DO UPDATE SET
+        name = EXCLUDED.name,
+        priority = EXCLUDED.priority
+      RETURNING task_id, external_id, name, priority, (xmax = 0) AS "inserted!"
+      "#,
+      task_id,
+      task.external_id,
+      name,
+      priority
+    )
+    .fetch_one(&mut *tx),
+  )
+  .await
+  .map_err(db::error_response)?;
+  db::commit(tx).await.map_err(db::error_response)?;
+
+  let status = if row.inserted {
+    StatusCode::CREATED
+  } else {
+    StatusCode::OK
+  };
+
+  Ok(reply.warn(warnings).data(
+    status,
+    TaskRow {
+      task_id: row.task_id,
+      name: row.name,
+      priority: row.priority,
+      external_id: row.external_id,
+    },
   ))
 }
 
 async fn update_task(
   State(pg_pool): State<PgPool>,
-  Path(task_id): Path<i32>,
-  Json(task): Json<UpdateTaskReq>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-  sqlx::query!(
-    "
-    UPDATE tasks SET
-      name = $2,
-      priority = $3
-    WHERE task_id = $1
-    ",
-    task_id,
-    task.name,
-    task.priority
+  Path(task_id): Path<i64>,
+  reply: Reply,
+  JsonBody(task, mut warnings): JsonBody<UpdateTaskReq>,
+) -> Result<Response, (StatusCode, String)> {
+  let name = task.name.map(|name| validation::name(name, &mut warnings));
+  let priority = validation::priority(task.priority, &mut warnings);
+
+  let mut tx = db::begin(&pg_pool).await.map_err(db::error_response)?;
+  timing::db(
+    traced_query!(
+      "
+      UPDATE tasks SET
+        name = $2,
+        priority = $3
+      WHERE task_id = $1
+      ",
+      task_id,
+      name,
+      priority
+    )
+    .execute(&mut *tx),
   )
-  .execute(&pg_pool)
   .await
-  .map_err(|e| {
-    (
-      StatusCode::INTERNAL_SERVER_ERROR,
-      json!({"success": false, "message": e.to_string()}).to_string(),
-    )
-  })?;
+  .map_err(db::error_response)?;
+  db::commit(tx).await.map_err(db::error_response)?;
 
-  Ok((StatusCode::OK, json!({"success": true}).to_string()))
+  Ok(reply.warn(warnings).done(StatusCode::OK))
 }
 
 async fn delete_task(
   State(pg_pool): State<PgPool>,
-  Path(task_id): Path<i32>,
-) -> Result<(StatusCode, String), (StatusCode, String)> {
-  sqlx::query!("DELETE FROM tasks WHERE task_id = $1", task_id)
-    .execute(&pg_pool)
+  Path(task_id): Path<i64>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  let mut tx = db::begin(&pg_pool).await.map_err(db::error_response)?;
+  timing::db(traced_query!("DELETE FROM tasks WHERE task_id = $1", task_id).execute(&mut *tx))
     .await
-    .map_err(|e| {
-      (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        json!({"success": false, "message": e.to_string()}).to_string(),
-      )
-    })?;
+    .map_err(db::error_response)?;
+  db::commit(tx).await.map_err(db::error_response)?;
 
-  Ok((StatusCode::OK, json!({"success": true}).to_string()))
+  Ok(reply.done(StatusCode::OK))
 }
 
 // Structs
-#[derive(Serialize)]
-struct TaskRow {
-  task_id: i32,
-  name: String,
-  priority: Option<i32>,
+#[derive(Clone)]
+struct AppState {
+  db_pool: PgPool,
+  exports: Arc<Exports>,
+  operations: Arc<Operations>,
+  ids: Arc<dyn IdGenerator>,
+  json_mode: JsonMode,
+  json_max_depth: JsonMaxDepth,
+  diagnostics: Arc<Diagnostics>,
+  admin_token: Option<Arc<str>>,
+  metrics: Arc<Metrics>,
+  envelope_usage: Arc<EnvelopeUsage>,
+  server_timing: bool,
+  row_cap: RowCap,
+  lease_ttl: LeaseTtl,
+  jobs: Arc<Jobs>,
+  duplicates: Arc<Duplicates>,
+  feature_usage: Arc<FeatureUsage>,
+  health_history: Arc<HealthHistory>,
+  task_stats: Arc<StatsCache>,
+  stream_slots: StreamSlots,
 }
 
-#[derive(Deserialize)]
-struct CreateTaskReq {
-  name: String,
-  priority: Option<i32>,
+impl FromRef<AppState> for PgPool {
+  fn from_ref(state: &AppState) -> Self {
+    state.db_pool.clone()
+  }
 }
 
-#[derive(Serialize)]
-struct CreateTaskRow {
-  task_id: i32,
+impl FromRef<AppState> for Arc<dyn IdGenerator> {
+  fn from_ref(state: &AppState) -> Self {
+    state.ids.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<Metrics> {
+  fn from_ref(state: &AppState) -> Self {
+    state.metrics.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<EnvelopeUsage> {
+  fn from_ref(state: &AppState) -> Self {
+    state.envelope_usage.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<Jobs> {
+  fn from_ref(state: &AppState) -> Self {
+    state.jobs.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<StatsCache> {
+  fn from_ref(state: &AppState) -> Self {
+    state.task_stats.clone()
+  }
+}
+
+impl FromRef<AppState> for StreamSlots {
+  fn from_ref(state: &AppState) -> Self {
+    state.stream_slots.clone()
+  }
+}
+
+impl FromRef<AppState> for RowCap {
+  fn from_ref(state: &AppState) -> Self {
+    state.row_cap
+  }
+}
+
+impl FromRef<AppState> for LeaseTtl {
+  fn from_ref(state: &AppState) -> Self {
+    state.lease_ttl
+  }
+}
+
+impl FromRef<AppState> for JsonMode {
+  fn from_ref(state: &AppState) -> Self {
+    state.json_mode
+  }
+}
+
+impl FromRef<AppState> for JsonMaxDepth {
+  fn from_ref(state: &AppState) -> Self {
+    state.json_max_depth
+  }
+}
+
+// Most rows GET /tasks answers with before asking for an export instead
+#[derive(Clone, Copy)]
+struct RowCap(i64);
+
+// Task streams that may be open at once
+#[derive(Clone)]
+struct StreamSlots(Arc<Semaphore>);
+
+impl ListSchema for TaskRow {
+  const SELECT: &'static str = "SELECT task_id, name, priority, external_id FROM tasks";
+  const SORTS: &'static [Sort] = &[
+    Sort {
+      name: "name",
+      column: "name",
+      text: true,
+    },
+    Sort {
+      name: "priority",
+      column: "priority",
+      text: false,
+    },
+    Sort {
+      name: "task_id",
+      column: "task_id",
+      text: false,
+    },
+  ];
+  const TIEBREAK: &'static str = "task_id";
+  const FILTERS: &'static [Filter] = &[
+    Filter {
+      name: "priority",
+      column: "priority",
+      kind: FilterKind::Int,
+    },
+    Filter {
+      name: "external_id",
+      column: "external_id",
+      kind: FilterKind::Text,
+    },
+    Filter {
+      name: "name",
+      column: "name",
+      kind: FilterKind::Contains,
+    },
+  ];
+  const EXTRA: &'static [&'static str] = &["locale"];
 }
 
 #[derive(Deserialize)]
-struct UpdateTaskReq {
-  name: Option<String>,
-  priority: Option<i32>,
+struct ListTasksParams {
+  locale: Option<String>,
+}
+
+impl KnownFields for CreateTaskReq {
+  const FIELDS: &'static [&'static str] = &["name", "priority"];
+}
+
+impl KnownFields for UpsertTaskReq {
+  const FIELDS: &'static [&'static str] = &["external_id", "name", "priority"];
+}
+
+impl KnownFields for UpdateTaskReq {
+  const FIELDS: &'static [&'static str] = &["name", "priority"];
 }
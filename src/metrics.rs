@@ -0,0 +1,299 @@
+// Request metrics
+//
+// Every request is counted by method, route and status code, and its
+// duration is added to a per-route total. The route label is the matched
+// route template (`/tasks/:task_id`) rather than the raw path, so the number
+// of series stays bounded no matter which ids clients ask for. Requests that
+// match no route share the `unmatched` label, and methods outside the
+// standard HTTP ones share `other`.
+//
+// Each route also totals the statements its requests ran and the time they
+// spent in the database (see the timing module), so a handler whose query
+// count grows with its result size (an N+1) stands out as a high
+// queries-per-request ratio.
+//
+// Expired task leases (see the queue module) are counted as well.
+//
+// Domain gauges come from `aggregate_task_stats`, which counts tasks every
+// STATS_INTERVAL (and whenever GET /tasks/stats refreshes its counts, see
+// the task_stats module) rather than on each scrape:
+// - `tasks_open`: tasks not completed yet
+// - `tasks_claimed`: open tasks a worker holds a lease on
+// - `tasks_created_last_hour`: tasks created in the hour before the run
+// Tasks have no due dates or tenants, so there is no overdue count and the
+// gauges carry no labels. They are left out until the first run finishes.
+//
+// GET /metrics serves the counters in the Prometheus text format.
+
+// Imports
+use axum::{
+  extract::{MatchedPath, Request, State},
+  http::{header, HeaderValue, Method},
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+
+use sqlx::PgPool;
+
+use std::{
+  collections::BTreeMap,
+  fmt::Write,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use axum_crud_rest_types::{timestamp::Timestamp, TaskStats};
+
+use crate::{db, diagnostics::traced_query, timing, AppState};
+
+// Constants
+pub const STATS_INTERVAL: Duration = Duration::from_secs(60);
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+// Store
+pub struct Metrics {
+  requests: Mutex<BTreeMap<RequestKey, RequestStats>>,
+  lease_expirations: AtomicU64,
+  task_stats: Mutex<Option<TaskStats>>,
+}
+
+impl Metrics {
+  pub fn new() -> Self {
+    Self {
+      requests: Mutex::new(BTreeMap::new()),
+      lease_expirations: AtomicU64::new(0),
+      task_stats: Mutex::new(None),
+    }
+  }
+
+  pub fn lease_expired(&self, count: u64) {
+    self.lease_expirations.fetch_add(count, Ordering::Relaxed);
+  }
+
+  // Requests handled so far, and how many of them failed with a 5xx
+  pub fn totals(&self) -> (u64, u64) {
+    self
+      .requests
+      .lock()
+      .unwrap()
+      .iter()
+      .fold((0, 0), |(requests, errors), (key, stats)| {
+        let failed = if key.status >= 500 { stats.count } else { 0 };
+        (requests + stats.count, errors + failed)
+      })
+  }
+
+  fn observe(&self, key: RequestKey, elapsed: Duration, (queries, db): (u64, Duration)) {
+    let mut requests = self.requests.lock().unwrap();
+    let stats = requests.entry(key).or_default();
+    stats.count += 1;
+    stats.duration += elapsed;
+    stats.queries += queries;
+    stats.db += db;
+  }
+
+  fn render(&self) -> String {
+    let requests = self.requests.lock().unwrap();
+    let mut out = String::new();
+
+    out.push_str("# HELP http_requests_total Requests handled, by route and status.\n");
+    out.push_str("# TYPE http_requests_total counter\n");
+    for (key, stats) in requests.iter() {
+      let _ = writeln!(
+        out,
+        "http_requests_total{{{}}} {}",
+        key.labels(),
+        stats.count
+      );
+    }
+
+    out.push_str("# HELP http_request_duration_seconds Time spent handling requests.\n");
+    out.push_str("# TYPE http_request_duration_seconds summary\n");
+    for (key, stats) in requests.iter() {
+      let labels = key.labels();
+      let _ = writeln!(
+        out,
+        "http_request_duration_seconds_sum{{{labels}}} {}",
+        stats.duration.as_secs_f64()
+      );
+      let _ = writeln!(
+        out,
+        "http_request_duration_seconds_count{{{labels}}} {}",
+        stats.count
+      );
+    }
+
+    out.push_str("# HELP http_request_db_queries Statements run while handling requests.\n");
+    out.push_str("# TYPE http_request_db_queries summary\n");
+    for (key, stats) in requests.iter() {
+      let labels = key.labels();
+      let _ = writeln!(
+        out,
+        "http_request_db_queries_sum{{{labels}}} {}",
+        stats.queries
+      );
+      let _ = writeln!(
+        out,
+        "http_request_db_queries_count{{{labels}}} {}",
+        stats.count
+      );
+    }
+
+    out.push_str("# HELP http_request_db_seconds Time requests spent in the database.\n");
+    out.push_str("# TYPE http_request_db_seconds summary\n");
+    for (key, stats) in requests.iter() {
+      let labels = key.labels();
+      let _ = writeln!(
+        out,
+        "http_request_db_seconds_sum{{{labels}}} {}",
+        stats.db.as_secs_f64()
+      );
+      let _ = writeln!(
+        out,
+        "http_request_db_seconds_count{{{labels}}} {}",
+        stats.count
+      );
+    }
+
+    out.push_str("# HELP task_lease_expirations_total Claimed tasks returned to the queue.\n");
+    out.push_str("# TYPE task_lease_expirations_total counter\n");
+    let _ = writeln!(
+      out,
+      "task_lease_expirations_total {}",
+      self.lease_expirations.load(Ordering::Relaxed)
+    );
+
+    if let Some(stats) = self.task_stats.lock().unwrap().as_ref() {
+      let gauges = [
+        ("tasks_open", "Tasks not completed yet.", stats.open),
+        (
+          "tasks_claimed",
+          "Open tasks under a worker's lease.",
+          stats.claimed,
+        ),
+        (
+          "tasks_created_last_hour",
+          "Tasks created in the last hour.",
+          stats.created_last_hour,
+        ),
+      ];
+      for (name, help, value) in gauges {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        let _ = writeln!(out, "{name} {value}");
+      }
+    }
+
+    out
+  }
+}
+
+// Functions
+pub async fn track_requests(State(state): State<AppState>, req: Request, next: Next) -> Response {
+  let method = method_label(req.method()).to_owned();
+  let route = req
+    .extensions()
+    .get::<MatchedPath>()
+    .map_or(UNMATCHED_ROUTE.to_owned(), |path| path.as_str().to_owned());
+
+  let started = Instant::now();
+  let res = next.run(req).await;
+
+  let key = RequestKey {
+    method,
+    route,
+    status: res.status().as_u16(),
+  };
+  let db_usage = timing::db_usage().unwrap_or_default();
+  state.metrics.observe(key, started.elapsed(), db_usage);
+
+  res
+}
+
+// The method as a label, with extension methods folded into one
+pub fn method_label(method: &Method) -> &'static str {
+  match *method {
+    Method::GET => "GET",
+    Method::HEAD => "HEAD",
+    Method::POST => "POST",
+    Method::PUT => "PUT",
+    Method::PATCH => "PATCH",
+    Method::DELETE => "DELETE",
+    Method::OPTIONS => "OPTIONS",
+    Method::CONNECT => "CONNECT",
+    Method::TRACE => "TRACE",
+    _ => "other",
+  }
+}
+
+pub async fn aggregate_task_stats(
+  pg_pool: &PgPool,
+  metrics: &Metrics,
+) -> Result<TaskStats, sqlx::Error> {
+  let row = db::retry_read(pg_pool, async |conn| {
+    traced_query!(
+      r#"
+      SELECT
+        count(*) AS "total!",
+        count(*) FILTER (WHERE completed_at IS NULL) AS "open!",
+        count(*) FILTER (WHERE completed_at IS NULL AND claimed_by IS NOT NULL) AS "claimed!",
+        count(*) FILTER (WHERE completed_at IS NOT NULL) AS "completed!",
+        count(*) FILTER (WHERE created_at > now() - INTERVAL '1 hour') AS "created_last_hour!"
+      FROM tasks
+      "#
+    )
+    .fetch_one(conn)
+    .await
+  })
+  .await?;
+
+  let stats = TaskStats {
+    total: row.total,
+    open: row.open,
+    claimed: row.claimed,
+    completed: row.completed,
+    created_last_hour: row.created_last_hour,
+    computed_at: Timestamp::now(),
+  };
+  *metrics.task_stats.lock().unwrap() = Some(stats.clone());
+  Ok(stats)
+}
+
+pub async fn get_metrics(State(metrics): State<Arc<Metrics>>) -> Response {
+  let mut res = metrics.render().into_response();
+  res.headers_mut().insert(
+    header::CONTENT_TYPE,
+    HeaderValue::from_static("text/plain; version=0.0.4"),
+  );
+  res
+}
+
+// Structs
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct RequestKey {
+  method: String,
+  route: String,
+  status: u16,
+}
+
+impl RequestKey {
+  fn labels(&self) -> String {
+    format!(
+      "method=\"{}\",route=\"{}\",status=\"{}\"",
+      self.method,
+      self.route.replace('\\', "\\\\").replace('"', "\\\""),
+      self.status
+    )
+  }
+}
+
+#[derive(Default)]
+struct RequestStats {
+  count: u64,
+  duration: Duration,
+  queries: u64,
+  db: Duration,
+}
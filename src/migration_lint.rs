@@ -0,0 +1,359 @@
+// Safe migration rules
+//
+// Migrations run against a live database, so statements that hold strong
+// locks for long (rewriting or scanning a whole table) block every request
+// touching that table until they finish. Pending migrations are checked
+// against these rules:
+// - `index-concurrently`: indexes on existing tables use CREATE INDEX
+//   CONCURRENTLY, in a migration starting with `-- no-transaction`
+// - `concurrently-in-transaction`: CONCURRENTLY needs `-- no-transaction`
+// - `column-type-change`: changing a column type rewrites the table
+// - `set-not-null`: use a NOT VALID check constraint and validate it later
+// - `constraint-not-valid`: foreign keys and checks are added NOT VALID and
+//   validated in a later migration
+// - `constraint-using-index`: unique and primary keys are added USING INDEX
+//   on an index built concurrently beforehand
+// - `add-column-not-null`: new NOT NULL columns need a default
+// - `volatile-default`: volatile column defaults rewrite the table
+// - `split-steps`: adding a column, backfilling it and constraining it are
+//   separate migrations
+//
+// A migration can opt out of a rule with a `-- lint: allow <rule>` line,
+// e.g. when the table is known to be small. Statements on tables created by
+// a pending migration are not checked, those tables hold no rows yet.
+//
+// `axum_crud_rest migrate --check` lints the pending migrations and exits
+// non-zero on findings. With MIGRATION_MODE=safe the server refuses to apply
+// migrations that have findings and runs the rest with a short lock_timeout,
+// so a migration waiting behind a long transaction fails instead of queueing
+// every other query behind it.
+
+// Imports
+use sqlx::{migrate::Migrator, Executor, PgPool};
+
+use std::collections::HashSet;
+
+// Constants
+const LOCK_TIMEOUT: &str = "5s";
+const VOLATILE_FUNCTIONS: &[&str] = &[
+  "CLOCK_TIMESTAMP",
+  "RANDOM",
+  "GEN_RANDOM_UUID",
+  "UUID_GENERATE_V4",
+  "TIMEOFDAY",
+];
+
+// Functions
+pub async fn check(pg_pool: &PgPool, migrator: &Migrator) -> Result<bool, sqlx::Error> {
+  let applied = applied_versions(pg_pool).await?;
+  let mut clean = true;
+  let mut created = HashSet::new();
+
+  for migration in migrator.iter().filter(|m| !applied.contains(&m.version)) {
+    let findings = lint(&migration.sql, migration.no_tx, &mut created);
+    if findings.is_empty() {
+      println!("ok    {}_{}", migration.version, migration.description);
+      continue;
+    }
+
+    clean = false;
+    println!("FAIL  {}_{}", migration.version, migration.description);
+    for finding in findings {
+      println!("      [{}] {}", finding.rule, finding.message);
+    }
+  }
+
+  Ok(clean)
+}
+
+pub async fn run_safe(pg_pool: &PgPool, migrator: &Migrator) -> Result<(), String> {
+  if !check(pg_pool, migrator).await.map_err(|e| e.to_string())? {
+    return Err("pending migrations break the safe migration rules".to_owned());
+  }
+
+  let mut conn = pg_pool.acquire().await.map_err(|e| e.to_string())?;
+  conn
+    .execute(format!("SET lock_timeout = '{LOCK_TIMEOUT}'").as_str())
+    .await
+    .map_err(|e| e.to_string())?;
+  let result = migrator.run(&mut *conn).await.map_err(|e| e.to_string());
+
+  // the setting is per session, keep it off pooled connections
+  let _ = conn.execute("RESET lock_timeout").await;
+
+  result
+}
+
+async fn applied_versions(pg_pool: &PgPool) -> Result<HashSet<i64>, sqlx::Error> {
+  let exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+    .fetch_one(pg_pool)
+    .await?;
+  if !exists {
+    return Ok(HashSet::new());
+  }
+
+  let versions: Vec<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+    .fetch_all(pg_pool)
+    .await?;
+
+  Ok(versions.into_iter().collect())
+}
+
+fn lint(sql: &str, no_tx: bool, created: &mut HashSet<String>) -> Vec<Finding> {
+  let allowed: HashSet<&str> = sql
+    .lines()
+    .filter_map(|line| line.trim().strip_prefix("-- lint: allow"))
+    .flat_map(|rules| rules.split([',', ' ']).filter(|rule| !rule.is_empty()))
+    .collect();
+
+  let mut findings = Vec::new();
+  let mut report = |rule: &'static str, message: String| {
+    if !allowed.contains(rule) {
+      findings.push(Finding { rule, message });
+    }
+  };
+
+  let mut altered = Vec::new();
+  let mut backfilled = Vec::new();
+  let mut validated = Vec::new();
+
+  for statement in statements(sql) {
+    let words = words(&statement);
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+
+    match words.as_slice() {
+      ["CREATE", "TABLE", rest @ ..] => {
+        if let Some(table) = name_after(rest, &[]) {
+          created.insert(table);
+        }
+      }
+      ["CREATE", "INDEX", rest @ ..] | ["CREATE", "UNIQUE", "INDEX", rest @ ..] => {
+        let concurrently = rest.first() == Some(&"CONCURRENTLY");
+        let on = rest.iter().position(|word| *word == "ON");
+        let table = on.and_then(|on| name_after(&rest[on + 1..], &["ONLY"]));
+
+        if concurrently && !no_tx {
+          report(
+            "concurrently-in-transaction",
+            "CREATE INDEX CONCURRENTLY cannot run inside a transaction, start the file with `-- no-transaction`".to_owned(),
+          );
+        }
+        if let Some(table) = table.filter(|table| !concurrently && !created.contains(table)) {
+          report(
+            "index-concurrently",
+            format!(
+              "index on {table} blocks writes while it builds, use CREATE INDEX CONCURRENTLY"
+            ),
+          );
+        }
+      }
+      ["ALTER", "TABLE", rest @ ..] => {
+        let Some(at) = rest
+          .iter()
+          .position(|word| !["IF", "EXISTS", "ONLY"].contains(word))
+        else {
+          continue;
+        };
+        let table = rest[at].trim_matches('"').to_lowercase();
+        if created.contains(&table) {
+          continue;
+        }
+
+        for action in actions(&rest[at + 1..]) {
+          lint_alter_action(&table, &action, &mut report, &mut altered, &mut validated);
+        }
+      }
+      ["UPDATE", rest @ ..] | ["DELETE", "FROM", rest @ ..] => {
+        if let Some(table) = name_after(rest, &["ONLY"]).filter(|table| !created.contains(table)) {
+          backfilled.push(table);
+        }
+      }
+      _ => {}
+    }
+  }
+
+  for table in backfilled.iter().filter(|table| altered.contains(table)) {
+    report(
+      "split-steps",
+      format!("{table} is altered and backfilled in one migration, split them so the backfill runs without the ALTER's lock"),
+    );
+  }
+  for table in validated.iter().filter(|table| altered.contains(table)) {
+    report(
+      "split-steps",
+      format!("a constraint on {table} is added and validated in one migration, validate it in a later one"),
+    );
+  }
+
+  findings
+}
+
+fn lint_alter_action(
+  table: &str,
+  action: &[&str],
+  report: &mut impl FnMut(&'static str, String),
+  altered: &mut Vec<String>,
+  validated: &mut Vec<String>,
+) {
+  let has = |seq: &[&str]| action.windows(seq.len()).any(|window| window == seq);
+
+  if action.first() == Some(&"VALIDATE") {
+    validated.push(table.to_owned());
+    return;
+  }
+  altered.push(table.to_owned());
+
+  if action.starts_with(&["ADD"]) && !has(&["CONSTRAINT"]) && !is_constraint_start(action.get(1)) {
+    if has(&["NOT", "NULL"]) && !has(&["DEFAULT"]) {
+      report(
+        "add-column-not-null",
+        format!("new NOT NULL column on {table} has no default, which fails on a table with rows"),
+      );
+    }
+    let default = action.iter().position(|word| *word == "DEFAULT");
+    if let Some(function) = default
+      .and_then(|at| action.get(at + 1))
+      .filter(|word| VOLATILE_FUNCTIONS.contains(word))
+    {
+      report(
+        "volatile-default",
+        format!("DEFAULT {function}() on {table} is volatile and rewrites the table, add the column without it and backfill"),
+      );
+    }
+    return;
+  }
+
+  if action.starts_with(&["ADD"]) {
+    if (has(&["FOREIGN", "KEY"]) || has(&["CHECK"])) && !has(&["NOT", "VALID"]) {
+      report(
+        "constraint-not-valid",
+        format!("constraint on {table} scans the table under lock, add it NOT VALID and validate it later"),
+      );
+    }
+    if (has(&["UNIQUE"]) || has(&["PRIMARY", "KEY"])) && !has(&["USING", "INDEX"]) {
+      report(
+        "constraint-using-index",
+        format!("constraint on {table} builds its index under lock, build it concurrently and add the constraint USING INDEX"),
+      );
+    }
+    return;
+  }
+
+  if action.starts_with(&["ALTER"]) && has(&["TYPE"]) {
+    report(
+      "column-type-change",
+      format!("changing a column type on {table} rewrites the table under an exclusive lock"),
+    );
+  }
+  if action.starts_with(&["ALTER"]) && has(&["SET", "NOT", "NULL"]) {
+    report(
+      "set-not-null",
+      format!("SET NOT NULL on {table} scans the table under lock, add a CHECK (... IS NOT NULL) NOT VALID constraint first"),
+    );
+  }
+}
+
+fn is_constraint_start(word: Option<&&str>) -> bool {
+  matches!(
+    word,
+    Some(&"CHECK" | &"UNIQUE" | &"PRIMARY" | &"FOREIGN" | &"EXCLUDE")
+  )
+}
+
+// Splits a script into statements, leaving out comments and keeping quoted
+// and dollar-quoted text (function bodies) intact
+fn statements(sql: &str) -> Vec<String> {
+  let mut statements = Vec::new();
+  let mut current = String::new();
+  let mut rest = sql;
+
+  while let Some(c) = rest.chars().next() {
+    if rest.starts_with("--") {
+      rest = rest.find('\n').map_or("", |end| &rest[end..]);
+      continue;
+    }
+
+    if c == '\'' {
+      let end = rest[1..].find('\'').map_or(rest.len(), |end| end + 2);
+      current.push_str("''");
+      rest = &rest[end..];
+      continue;
+    }
+
+    if c == '$' {
+      if let Some(tag_end) = rest[1..].find('$') {
+        let tag = &rest[..tag_end + 2];
+        if tag[1..tag.len() - 1]
+          .chars()
+          .all(|c| c.is_alphanumeric() || c == '_')
+        {
+          let end = rest[tag.len()..]
+            .find(tag)
+            .map_or(rest.len(), |end| end + 2 * tag.len());
+          current.push_str("$$");
+          rest = &rest[end..];
+          continue;
+        }
+      }
+    }
+
+    if c == ';' {
+      statements.push(std::mem::take(&mut current));
+    } else {
+      current.push(c);
+    }
+    rest = &rest[c.len_utf8()..];
+  }
+  statements.push(current);
+
+  statements.retain(|statement| !statement.trim().is_empty());
+  statements
+}
+
+fn words(statement: &str) -> Vec<String> {
+  statement
+    .replace('(', " ( ")
+    .replace(')', " ) ")
+    .replace(',', " , ")
+    .split_whitespace()
+    .map(str::to_uppercase)
+    .collect()
+}
+
+// The first word after `rest` that is not one of the `skip` keywords,
+// as a lowercase name
+fn name_after(rest: &[&str], skip: &[&str]) -> Option<String> {
+  rest
+    .iter()
+    .filter(|word| !skip.contains(word) && !["IF", "NOT", "EXISTS"].contains(word))
+    .map(|word| word.trim_matches('"').to_lowercase())
+    .next()
+}
+
+// Splits the actions of an ALTER TABLE on commas outside parentheses
+fn actions<'a>(words: &[&'a str]) -> Vec<Vec<&'a str>> {
+  let mut actions = vec![Vec::new()];
+  let mut depth = 0;
+
+  for word in words {
+    match *word {
+      "(" => depth += 1,
+      ")" => depth -= 1,
+      "," if depth == 0 => {
+        actions.push(Vec::new());
+        continue;
+      }
+      _ => {}
+    }
+    actions.last_mut().unwrap().push(*word);
+  }
+
+  actions.retain(|action| !action.is_empty());
+  actions
+}
+
+// Structs
+struct Finding {
+  rule: &'static str,
+  message: String,
+}
@@ -0,0 +1,254 @@
+// Long-running operations
+//
+// Anything that is too slow to answer inline (exports today) is started as
+// an operation. Clients poll GET /operations/:id for status and progress,
+// follow the result link once it succeeds and may ask for cancellation via
+// POST /operations/:id/cancel.
+//
+// Cancellation is cooperative: workers call `checkpoint` at every batch
+// boundary and stop when it reports `Cancelled`. Batches completed before
+// that point are kept (each batch commits on its own), nothing after it
+// runs, and the operation ends as `cancelled` with its progress showing how
+// far it got. Operations that only read data, like exports, simply discard
+// their partial result.
+//
+// Finished operations (succeeded, failed or cancelled) are kept for
+// OPERATION_RETENTION_SECS (default 3600) and then removed by the
+// `expire_operations` job, after which polling them answers 404.
+
+// Imports
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  response::Response,
+};
+
+use serde::Serialize;
+use serde_json::json;
+
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use crate::{response::Reply, AppState};
+
+// Constants
+pub const EXPIRE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Store
+pub struct Operations {
+  operations: Mutex<HashMap<u64, Operation>>,
+  next_id: AtomicU64,
+  retention: Duration,
+}
+
+impl Operations {
+  pub fn new(retention: Duration) -> Self {
+    Self {
+      operations: Mutex::new(HashMap::new()),
+      next_id: AtomicU64::new(1),
+      retention,
+    }
+  }
+
+  // Removes the operations that finished longer than the retention ago
+  pub fn expire(&self) {
+    self.operations.lock().unwrap().retain(|_, operation| {
+      operation
+        .finished_at
+        .is_none_or(|finished_at| finished_at.elapsed() < self.retention)
+    });
+  }
+
+  pub fn start(self: &Arc<Self>, kind: OperationKind) -> OperationHandle {
+    let operation_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let operation = Operation {
+      kind,
+      status: OperationStatus::Pending,
+      progress: 0,
+      error: None,
+      cancel_requested: false,
+      finished_at: None,
+    };
+
+    self
+      .operations
+      .lock()
+      .unwrap()
+      .insert(operation_id, operation);
+
+    OperationHandle {
+      operation_id,
+      operations: self.clone(),
+    }
+  }
+
+  fn update(&self, operation_id: u64, f: impl FnOnce(&mut Operation)) {
+    if let Some(operation) = self.operations.lock().unwrap().get_mut(&operation_id) {
+      f(operation);
+    }
+  }
+}
+
+// Handle given to the task doing the work
+pub struct OperationHandle {
+  operation_id: u64,
+  operations: Arc<Operations>,
+}
+
+impl OperationHandle {
+  pub fn id(&self) -> u64 {
+    self.operation_id
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    let operations = self.operations.operations.lock().unwrap();
+    operations
+      .get(&self.operation_id)
+      .is_none_or(|operation| operation.cancel_requested)
+  }
+
+  pub fn running(&self) {
+    self.operations.update(self.operation_id, |operation| {
+      if let OperationStatus::Pending = operation.status {
+        operation.status = OperationStatus::Running;
+      }
+    });
+  }
+
+  pub fn checkpoint(&self, done: u64, total: u64) -> Result<(), Cancelled> {
+    let progress = match total {
+      0 => 100,
+      _ => (done.min(total) * 100 / total) as u8,
+    };
+
+    let mut operations = self.operations.operations.lock().unwrap();
+    match operations.get_mut(&self.operation_id) {
+      Some(operation) if !operation.cancel_requested => {
+        operation.progress = progress;
+        Ok(())
+      }
+      _ => Err(Cancelled),
+    }
+  }
+
+  pub fn succeed(&self) {
+    self.operations.update(self.operation_id, |operation| {
+      operation.status = OperationStatus::Succeeded;
+      operation.progress = 100;
+      operation.finished_at = Some(Instant::now());
+    });
+  }
+
+  pub fn fail(&self, error: String) {
+    self.operations.update(self.operation_id, |operation| {
+      operation.status = OperationStatus::Failed;
+      operation.error = Some(error);
+      operation.finished_at = Some(Instant::now());
+    });
+  }
+
+  pub fn cancelled(&self) {
+    self.operations.update(self.operation_id, |operation| {
+      operation.status = OperationStatus::Cancelled;
+      operation.finished_at = Some(Instant::now());
+    });
+  }
+}
+
+// Functions
+pub async fn get_operation(
+  State(state): State<AppState>,
+  Path(operation_id): Path<u64>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  let operations = state.operations.operations.lock().unwrap();
+  let operation = operations.get(&operation_id).ok_or_else(not_found)?;
+
+  let result_url = match (operation.kind, operation.status) {
+    (OperationKind::Export, OperationStatus::Succeeded) => state.exports.download_url(operation_id),
+    _ => None,
+  };
+
+  Ok(reply.data(
+    StatusCode::OK,
+    json!({
+      "operation_id": operation_id,
+      "kind": operation.kind,
+      "status": operation.status,
+      "progress": operation.progress,
+      "error": operation.error,
+      "cancel_requested": operation.cancel_requested,
+      "result_url": result_url,
+    }),
+  ))
+}
+
+pub async fn cancel_operation(
+  State(state): State<AppState>,
+  Path(operation_id): Path<u64>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  let mut operations = state.operations.operations.lock().unwrap();
+  let operation = operations.get_mut(&operation_id).ok_or_else(not_found)?;
+
+  match operation.status {
+    OperationStatus::Pending => {
+      operation.cancel_requested = true;
+      operation.status = OperationStatus::Cancelled;
+      operation.finished_at = Some(Instant::now());
+    }
+    OperationStatus::Running => operation.cancel_requested = true,
+    _ => {
+      return Err((
+        StatusCode::CONFLICT,
+        json!({"success": false, "message": "Operation has already finished"}).to_string(),
+      ))
+    }
+  }
+
+  Ok(reply.data(
+    StatusCode::ACCEPTED,
+    json!({"operation_id": operation_id, "status": operation.status}),
+  ))
+}
+
+fn not_found() -> (StatusCode, String) {
+  (
+    StatusCode::NOT_FOUND,
+    json!({"success": false, "message": "Operation not found"}).to_string(),
+  )
+}
+
+// Structs
+pub struct Cancelled;
+
+struct Operation {
+  kind: OperationKind,
+  status: OperationStatus,
+  progress: u8,
+  error: Option<String>,
+  cancel_requested: bool,
+  finished_at: Option<Instant>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationKind {
+  Export,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OperationStatus {
+  Pending,
+  Running,
+  Succeeded,
+  Failed,
+  Cancelled,
+}
@@ -0,0 +1,253 @@
+// Work queue consumption
+//
+// GET /tasks/next returns the task a worker should pick up next: the
+// unclaimed task with the highest priority, oldest first, with tasks
+// without a priority last. Tasks have no status or dependencies, so every
+// unclaimed task counts as open.
+//
+// With `?claim=true&worker=<name>` the task is claimed in the same
+// statement, so concurrent workers never get the same task. Rows another
+// worker is claiming right now are skipped rather than waited for.
+//
+// A claim is a lease of TASK_LEASE_SECS (default 300). The worker either
+// finishes the task with POST /tasks/:task_id/complete or extends the lease
+// with POST /tasks/:task_id/lease/renew before it runs out, both with
+// `{"worker": "<name>"}`. Expired leases are released by the
+// `release_expired_leases` job every few seconds, which puts the task back
+// in the queue and counts the expiration in /metrics. Completed tasks stay
+// claimed by their worker.
+
+// Imports
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::Response,
+};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use sqlx::PgPool;
+
+use std::time::Duration;
+
+use axum_crud_rest_types::{timestamp::Timestamp, ClaimedTask, LeaseReq, RenewedLease, TaskRow};
+
+use crate::{
+  db,
+  diagnostics::{traced_query, traced_query_as},
+  extract::{JsonBody, KnownFields},
+  feature_usage,
+  metrics::Metrics,
+  response::Reply,
+  timing,
+};
+
+// Constants
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+// Functions
+pub async fn next_task(
+  State(pg_pool): State<PgPool>,
+  State(lease_ttl): State<LeaseTtl>,
+  Query(params): Query<NextTaskParams>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  if !params.claim {
+    let row = db::retry_read(&pg_pool, async |conn| {
+      traced_query_as!(
+        TaskRow,
+        "
+        SELECT task_id, name, priority, external_id FROM tasks
+        WHERE claimed_by IS NULL
+        ORDER BY priority DESC NULLS LAST, created_at, task_id
+        LIMIT 1
+        "
+      )
+      .fetch_optional(conn)
+      .await
+    })
+    .await
+    .map_err(db::error_response)?
+    .ok_or_else(no_open_tasks)?;
+
+    return Ok(reply.data(StatusCode::OK, row));
+  }
+
+  feature_usage::used("claim");
+  let worker = required_worker(params.worker)?;
+
+  let mut tx = db::begin(&pg_pool).await.map_err(db::error_response)?;
+  let row = timing::db(
+    traced_query!(
+      r#"
+      UPDATE tasks SET
+        claimed_by = $1,
+        claimed_at = now(),
+        lease_expires_at = now() + make_interval(secs => $2::INT)
+      WHERE task_id = (
+        SELECT task_id FROM tasks
+        WHERE claimed_by IS NULL
+        ORDER BY priority DESC NULLS LAST, created_at, task_id
+        LIMIT 1
+        FOR UPDATE SKIP LOCKED
+      )
+      RETURNING
+        task_id, name, priority, external_id,
+        claimed_by AS "claimed_by!",
+        (EXTRACT(EPOCH FROM lease_expires_at) * 1000)::BIGINT AS "lease_expires_at!"
+      "#,
+      worker,
+      lease_ttl.secs()
+    )
+    .fetch_optional(&mut *tx),
+  )
+  .await
+  .map_err(db::error_response)?
+  .ok_or_else(no_open_tasks)?;
+  db::commit(tx).await.map_err(db::error_response)?;
+
+  Ok(reply.data(
+    StatusCode::OK,
+    ClaimedTask {
+      task_id: row.task_id,
+      name: row.name,
+      priority: row.priority,
+      external_id: row.external_id,
+      claimed_by: row.claimed_by,
+      lease_expires_at: Timestamp(row.lease_expires_at),
+    },
+  ))
+}
+
+pub async fn renew_lease(
+  State(pg_pool): State<PgPool>,
+  State(lease_ttl): State<LeaseTtl>,
+  Path(task_id): Path<i64>,
+  reply: Reply,
+  JsonBody(req, warnings): JsonBody<LeaseReq>,
+) -> Result<Response, (StatusCode, String)> {
+  let worker = required_worker(Some(req.worker))?;
+
+  let mut tx = db::begin(&pg_pool).await.map_err(db::error_response)?;
+  let row = timing::db(
+    traced_query!(
+      r#"
+      UPDATE tasks SET lease_expires_at = now() + make_interval(secs => $3::INT)
+      WHERE task_id = $1 AND claimed_by = $2 AND lease_expires_at > now()
+      RETURNING (EXTRACT(EPOCH FROM lease_expires_at) * 1000)::BIGINT AS "lease_expires_at!"
+      "#,
+      task_id,
+      worker,
+      lease_ttl.secs()
+    )
+    .fetch_optional(&mut *tx),
+  )
+  .await
+  .map_err(db::error_response)?
+  .ok_or_else(lease_not_held)?;
+  db::commit(tx).await.map_err(db::error_response)?;
+
+  Ok(reply.warn(warnings).data(
+    StatusCode::OK,
+    RenewedLease {
+      task_id,
+      lease_expires_at: Timestamp(row.lease_expires_at),
+    },
+  ))
+}
+
+pub async fn complete_task(
+  State(pg_pool): State<PgPool>,
+  Path(task_id): Path<i64>,
+  reply: Reply,
+  JsonBody(req, warnings): JsonBody<LeaseReq>,
+) -> Result<Response, (StatusCode, String)> {
+  let worker = required_worker(Some(req.worker))?;
+
+  let mut tx = db::begin(&pg_pool).await.map_err(db::error_response)?;
+  let completed = timing::db(
+    traced_query!(
+      "
+      UPDATE tasks SET completed_at = now(), lease_expires_at = NULL
+      WHERE task_id = $1 AND claimed_by = $2 AND lease_expires_at > now()
+      ",
+      task_id,
+      worker
+    )
+    .execute(&mut *tx),
+  )
+  .await
+  .map_err(db::error_response)?
+  .rows_affected();
+  db::commit(tx).await.map_err(db::error_response)?;
+
+  if completed == 0 {
+    return Err(lease_not_held());
+  }
+
+  Ok(reply.warn(warnings).done(StatusCode::OK))
+}
+
+pub async fn release_expired_leases(pg_pool: &PgPool, metrics: &Metrics) -> Result<(), String> {
+  let released = sqlx::query!(
+    "
+    UPDATE tasks SET claimed_by = NULL, claimed_at = NULL, lease_expires_at = NULL
+    WHERE lease_expires_at <= now()
+    "
+  )
+  .execute(pg_pool)
+  .await
+  .map_err(|e| e.to_string())?;
+
+  metrics.lease_expired(released.rows_affected());
+  Ok(())
+}
+
+fn required_worker(worker: Option<String>) -> Result<String, (StatusCode, String)> {
+  worker.filter(|worker| !worker.is_empty()).ok_or_else(|| {
+    (
+      StatusCode::UNPROCESSABLE_ENTITY,
+      json!({"success": false, "message": "Claims need a `worker` name"}).to_string(),
+    )
+  })
+}
+
+fn no_open_tasks() -> (StatusCode, String) {
+  (
+    StatusCode::NOT_FOUND,
+    json!({"success": false, "message": "No unclaimed tasks"}).to_string(),
+  )
+}
+
+fn lease_not_held() -> (StatusCode, String) {
+  (
+    StatusCode::CONFLICT,
+    json!({
+      "success": false,
+      "message": "The worker holds no active lease on this task",
+    })
+    .to_string(),
+  )
+}
+
+// Structs
+#[derive(Clone, Copy)]
+pub struct LeaseTtl(pub Duration);
+
+impl LeaseTtl {
+  fn secs(self) -> i32 {
+    self.0.as_secs().try_into().unwrap_or(i32::MAX)
+  }
+}
+
+#[derive(Deserialize)]
+pub struct NextTaskParams {
+  #[serde(default)]
+  claim: bool,
+  worker: Option<String>,
+}
+
+impl KnownFields for LeaseReq {
+  const FIELDS: &'static [&'static str] = &["worker"];
+}
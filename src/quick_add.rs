@@ -0,0 +1,242 @@
+// Natural-language quick add
+//
+// POST /tasks/quick takes a single line such as
+// "Pay rent tomorrow 5pm #finance !high" and creates the task it describes,
+// returning it with its due date and tags. These words are pulled out of
+// the text; everything else becomes the task name:
+// - priority: `!low`, `!medium`, `!high` or `!1` to `!5`
+// - tags: `#<tag>`, kept as written, each once
+// - due day: `today`, `tomorrow`, a weekday (`friday`, `fri`: the next one
+//   after today) or a date (`2026-10-20`)
+// - due time: `5pm`, `5:30pm` or `17:30`
+// A day without a time is due at the end of that day; a time without a day
+// is due at its next occurrence. Dates and times are read in UTC, since
+// requests carry no time zone. When a kind of word appears more than once,
+// the last one counts.
+
+// Imports
+use axum::{extract::State, http::StatusCode, response::Response};
+
+use serde_json::json;
+
+use sqlx::PgPool;
+
+use std::sync::Arc;
+
+use axum_crud_rest_types::{timestamp::Timestamp, QuickAddReq, QuickTask};
+
+use crate::{
+  db,
+  diagnostics::traced_query,
+  extract::{JsonBody, KnownFields},
+  ids::IdGenerator,
+  response::Reply,
+  timing,
+};
+
+// Constants
+const DAY_MS: i64 = 86_400_000;
+const MINUTE_MS: i64 = 60_000;
+const WEEKDAYS: [[&str; 2]; 7] = [
+  ["sunday", "sun"],
+  ["monday", "mon"],
+  ["tuesday", "tue"],
+  ["wednesday", "wed"],
+  ["thursday", "thu"],
+  ["friday", "fri"],
+  ["saturday", "sat"],
+];
+
+// Functions
+pub async fn quick_add_task(
+  State(pg_pool): State<PgPool>,
+  State(ids): State<Arc<dyn IdGenerator>>,
+  reply: Reply,
+  JsonBody(req, warnings): JsonBody<QuickAddReq>,
+) -> Result<Response, (StatusCode, String)> {
+  let parsed = parse(&req.text, Timestamp::now());
+
+  if parsed.name.is_empty() {
+    return Err((
+      StatusCode::UNPROCESSABLE_ENTITY,
+      json!({"success": false, "message": "Quick add text has no task name"}).to_string(),
+    ));
+  }
+
+  let task_id = ids.next_id()?;
+  let due_at = parsed.due_at.map(|due_at| due_at.0);
+  let mut tx = db::begin(&pg_pool).await.map_err(db::error_response)?;
+  let row = timing::db(
+    traced_query!(
+      r#"
+      INSERT INTO tasks (task_id, name, priority, due_at, tags)
+      VALUES (
+        COALESCE($1, nextval('tasks_task_id_seq')),
+        $2,
+        $3,
+        to_timestamp($4::BIGINT / 1000.0),
+        $5
+      )
+      RETURNING
+        task_id, name, priority, external_id, tags,
+        (EXTRACT(EPOCH FROM due_at) * 1000)::BIGINT AS due_at
+      "#,
+      task_id,
+      parsed.name,
+      parsed.priority,
+      due_at,
+      &parsed.tags
+    )
+    .fetch_one(&mut *tx),
+  )
+  .await
+  .map_err(db::error_response)?;
+  db::commit(tx).await.map_err(db::error_response)?;
+
+  Ok(reply.warn(warnings).data(
+    StatusCode::CREATED,
+    QuickTask {
+      task_id: row.task_id,
+      name: row.name,
+      priority: row.priority,
+      external_id: row.external_id,
+      due_at: row.due_at.map(Timestamp),
+      tags: row.tags,
+    },
+  ))
+}
+
+fn parse(text: &str, now: Timestamp) -> Parsed {
+  let today = now.0.div_euclid(DAY_MS);
+  let mut parsed = Parsed {
+    name: String::new(),
+    priority: None,
+    due_at: None,
+    tags: Vec::new(),
+  };
+  let (mut day, mut time) = (None, None);
+  let mut words = Vec::new();
+
+  for word in text.split_whitespace() {
+    if let Some(p) = word.strip_prefix('!').and_then(parse_priority) {
+      parsed.priority = Some(p);
+    } else if let Some(tag) = word.strip_prefix('#').filter(|tag| !tag.is_empty()) {
+      if !parsed.tags.iter().any(|known| known == tag) {
+        parsed.tags.push(tag.to_owned());
+      }
+    } else if let Some(d) = parse_day(word, today) {
+      day = Some(d);
+    } else if let Some(t) = parse_time(word) {
+      time = Some(t);
+    } else {
+      words.push(word);
+    }
+  }
+
+  parsed.name = words.join(" ");
+  parsed.due_at = match (day, time) {
+    (Some(day), Some(minutes)) => Some(day * DAY_MS + minutes * MINUTE_MS),
+    // the last second of the day
+    (Some(day), None) => Some((day + 1) * DAY_MS - 1000),
+    (None, Some(minutes)) => {
+      let at = today * DAY_MS + minutes * MINUTE_MS;
+      Some(if at <= now.0 { at + DAY_MS } else { at })
+    }
+    (None, None) => None,
+  }
+  .map(Timestamp);
+
+  parsed
+}
+
+fn parse_priority(marker: &str) -> Option<i32> {
+  match marker.to_lowercase().as_str() {
+    "low" => Some(1),
+    "medium" | "med" => Some(3),
+    "high" => Some(5),
+    n => n.parse().ok().filter(|p| (1..=5).contains(p)),
+  }
+}
+
+// The day `word` names, in days since the unix epoch
+fn parse_day(word: &str, today: i64) -> Option<i64> {
+  let word = word.to_lowercase();
+  match word.as_str() {
+    "today" => return Some(today),
+    "tomorrow" => return Some(today + 1),
+    _ => {}
+  }
+
+  if let Some(weekday) = WEEKDAYS
+    .iter()
+    .position(|names| names.contains(&word.as_str()))
+  {
+    // 1970-01-01 was a Thursday
+    let current = (today + 4).rem_euclid(7);
+    return Some(today + (weekday as i64 - current - 1).rem_euclid(7) + 1);
+  }
+
+  let mut parts = word.splitn(3, '-');
+  let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+  if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+    return None;
+  }
+  let number = |part: &str| -> Option<i64> {
+    part
+      .bytes()
+      .all(|b| b.is_ascii_digit())
+      .then(|| part.parse().ok())?
+  };
+  let date = Timestamp::from_utc_date(number(year)?, number(month)?, number(day)?)?;
+  Some(date.0 / DAY_MS)
+}
+
+// The time of day `word` names, in minutes after midnight
+fn parse_time(word: &str) -> Option<i64> {
+  let word = word.to_lowercase();
+  let (clock, meridiem) = match word.strip_suffix("am") {
+    Some(clock) => (clock, Some(0)),
+    None => match word.strip_suffix("pm") {
+      Some(clock) => (clock, Some(12)),
+      None => (word.as_str(), None),
+    },
+  };
+
+  let (hour, minute) = match clock.split_once(':') {
+    Some((hour, minute)) if minute.len() == 2 => (hour, minute),
+    Some(_) => return None,
+    // a bare number is only a time with am/pm
+    None if meridiem.is_some() => (clock, "00"),
+    None => return None,
+  };
+  if hour.is_empty()
+    || hour.len() > 2
+    || !(hour.bytes().chain(minute.bytes())).all(|b| b.is_ascii_digit())
+  {
+    return None;
+  }
+  let (hour, minute): (i64, i64) = (hour.parse().ok()?, minute.parse().ok()?);
+  if minute > 59 {
+    return None;
+  }
+
+  let hour = match meridiem {
+    Some(offset) if (1..=12).contains(&hour) => hour % 12 + offset,
+    Some(_) => return None,
+    None if hour <= 23 => hour,
+    None => return None,
+  };
+  Some(hour * 60 + minute)
+}
+
+// Structs
+struct Parsed {
+  name: String,
+  priority: Option<i32>,
+  due_at: Option<Timestamp>,
+  tags: Vec<String>,
+}
+
+impl KnownFields for QuickAddReq {
+  const FIELDS: &'static [&'static str] = &["text"];
+}
@@ -0,0 +1,150 @@
+// Consul service registration
+//
+// When CONSUL_HTTP_ADDR is set the instance registers itself with the local
+// Consul agent on startup, keeps a TTL health check updated from a database
+// ping and deregisters as soon as shutdown starts, so Consul stops sending
+// traffic while in-flight requests finish. The agent API is plain HTTP/1.1,
+// so requests are written directly on a TCP connection; CONSUL_HTTP_ADDR is
+// `host:port` or `http://host:port`. Each call gives up after
+// CONSUL_TIMEOUT, so a hung agent cannot stall startup or shutdown.
+
+// Imports
+use serde_json::json;
+
+use sqlx::PgPool;
+
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::TcpStream,
+  task::JoinHandle,
+  time::timeout,
+};
+
+use std::{io, net::SocketAddr, time::Duration};
+
+// Constants
+const CHECK_TTL: Duration = Duration::from_secs(30);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+const CONSUL_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Registration
+pub struct Registration {
+  consul_addr: String,
+  service_id: String,
+  heartbeat: JoinHandle<()>,
+}
+
+impl Registration {
+  pub async fn register(
+    consul_addr: String,
+    service_name: String,
+    advertise_addr: SocketAddr,
+    db_pool: PgPool,
+  ) -> io::Result<Self> {
+    let consul_addr = agent_addr(&consul_addr)?;
+    let service_id = format!("{service_name}-{}", advertise_addr.port());
+    let body = json!({
+      "ID": service_id,
+      "Name": service_name,
+      "Address": advertise_addr.ip().to_string(),
+      "Port": advertise_addr.port(),
+      "Check": {
+        "CheckID": check_id(&service_id),
+        "TTL": format!("{}s", CHECK_TTL.as_secs()),
+        "DeregisterCriticalServiceAfter": "5m",
+      },
+    });
+
+    consul_put(
+      &consul_addr,
+      "/v1/agent/service/register",
+      &body.to_string(),
+    )
+    .await?;
+    println!("Registered {service_id} with Consul at {consul_addr}");
+
+    let heartbeat = tokio::spawn(heartbeat(
+      consul_addr.clone(),
+      check_id(&service_id),
+      db_pool,
+    ));
+
+    Ok(Self {
+      consul_addr,
+      service_id,
+      heartbeat,
+    })
+  }
+
+  pub async fn deregister(self) {
+    self.heartbeat.abort();
+
+    let path = format!("/v1/agent/service/deregister/{}", self.service_id);
+    match consul_put(&self.consul_addr, &path, "").await {
+      Ok(()) => println!("Deregistered {} from Consul", self.service_id),
+      Err(e) => eprintln!("Could not deregister {} from Consul: {e}", self.service_id),
+    }
+  }
+}
+
+// Functions
+async fn heartbeat(consul_addr: String, check_id: String, db_pool: PgPool) {
+  let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+  loop {
+    interval.tick().await;
+
+    let status = match sqlx::query("SELECT 1").execute(&db_pool).await {
+      Ok(_) => "pass",
+      Err(_) => "fail",
+    };
+
+    let path = format!("/v1/agent/check/{status}/{check_id}");
+    if let Err(e) = consul_put(&consul_addr, &path, "").await {
+      eprintln!("Could not update Consul health check: {e}");
+    }
+  }
+}
+
+async fn consul_put(consul_addr: &str, path: &str, body: &str) -> io::Result<()> {
+  timeout(CONSUL_TIMEOUT, request(consul_addr, path, body))
+    .await
+    .map_err(|_| io::Error::other(format!("Consul did not answer {path} in time")))?
+}
+
+async fn request(consul_addr: &str, path: &str, body: &str) -> io::Result<()> {
+  let mut stream = TcpStream::connect(consul_addr).await?;
+
+  let request = format!(
+    "PUT {path} HTTP/1.1\r\nHost: {consul_addr}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+    body.len()
+  );
+  stream.write_all(request.as_bytes()).await?;
+
+  let mut response = Vec::new();
+  stream.read_to_end(&mut response).await?;
+
+  let response = String::from_utf8_lossy(&response);
+  let status_line = response.lines().next().unwrap_or_default();
+  match status_line.split_whitespace().nth(1) {
+    Some(code) if code.starts_with('2') => Ok(()),
+    _ => Err(io::Error::other(format!(
+      "Consul answered {path} with {status_line:?}"
+    ))),
+  }
+}
+
+// CONSUL_HTTP_ADDR as host:port, without an http:// scheme
+pub fn agent_addr(consul_addr: &str) -> io::Result<String> {
+  let addr = consul_addr.trim().trim_end_matches('/');
+  if addr.starts_with("https://") {
+    return Err(io::Error::other(format!(
+      "CONSUL_HTTP_ADDR {consul_addr:?} uses https, only plain http is supported"
+    )));
+  }
+  Ok(addr.strip_prefix("http://").unwrap_or(addr).to_owned())
+}
+
+fn check_id(service_id: &str) -> String {
+  format!("service:{service_id}")
+}
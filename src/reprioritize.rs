@@ -0,0 +1,126 @@
+// Bulk reprioritization
+//
+// POST /tasks/reprioritize takes `{"task_ids": [...]}`, the tasks ranked
+// from most to least important, and spreads them evenly over priorities 5
+// down to 1. Up to five tasks get one priority each (5, 4, 3, ...); longer
+// lists are cut into five equal bands. Tasks with the same priority keep
+// their queue order (oldest first), since tasks have no position column.
+//
+// All tasks are updated by one statement in one transaction. If any id
+// does not exist nothing changes and the missing ids are listed in a 404.
+// Instead of one log line per task, a single `tasks_reprioritized` event
+// records the whole change.
+
+// Imports
+use axum::{extract::State, http::StatusCode, response::Response};
+
+use serde_json::json;
+
+use sqlx::PgPool;
+
+use std::collections::HashSet;
+
+use axum_crud_rest_types::ReprioritizeReq;
+
+use crate::{
+  db,
+  diagnostics::traced_query_scalar,
+  extract::{JsonBody, KnownFields},
+  response::Reply,
+  timing,
+};
+
+// Constants
+const MAX_TASKS: usize = 1000;
+const BANDS: usize = 5;
+
+// Functions
+pub async fn reprioritize_tasks(
+  State(pg_pool): State<PgPool>,
+  reply: Reply,
+  JsonBody(req, warnings): JsonBody<ReprioritizeReq>,
+) -> Result<Response, (StatusCode, String)> {
+  let task_ids = req.task_ids;
+
+  // longer lists are rejected while the body is parsed
+  if task_ids.is_empty() {
+    return Err(invalid("`task_ids` must not be empty".to_owned()));
+  }
+
+  let mut seen = HashSet::new();
+  if let Some(task_id) = task_ids.iter().find(|task_id| !seen.insert(**task_id)) {
+    return Err(invalid(format!("Task {task_id} is listed more than once")));
+  }
+
+  let priorities = priorities(task_ids.len());
+
+  let mut tx = db::begin(&pg_pool).await.map_err(db::error_response)?;
+  let updated = timing::db(
+    traced_query_scalar!(
+      "
+      UPDATE tasks SET priority = ranked.priority
+      FROM unnest($1::BIGINT[], $2::INT[]) AS ranked (task_id, priority)
+      WHERE tasks.task_id = ranked.task_id
+      RETURNING tasks.task_id
+      ",
+      &task_ids,
+      &priorities
+    )
+    .fetch_all(&mut *tx),
+  )
+  .await
+  .map_err(db::error_response)?;
+
+  if updated.len() < task_ids.len() {
+    // dropping the transaction rolls the update back
+    let updated: HashSet<i64> = updated.into_iter().collect();
+    let missing: Vec<i64> = task_ids
+      .into_iter()
+      .filter(|task_id| !updated.contains(task_id))
+      .collect();
+
+    return Err((
+      StatusCode::NOT_FOUND,
+      json!({"success": false, "message": "Some tasks do not exist", "missing": missing})
+        .to_string(),
+    ));
+  }
+  db::commit(tx).await.map_err(db::error_response)?;
+
+  eprintln!(
+    "{}",
+    json!({
+      "event": "tasks_reprioritized",
+      "count": task_ids.len(),
+    })
+  );
+
+  let tasks: Vec<_> = task_ids
+    .into_iter()
+    .zip(priorities)
+    .map(|(task_id, priority)| json!({"task_id": task_id, "priority": priority}))
+    .collect();
+
+  Ok(reply.warn(warnings).data(StatusCode::OK, tasks))
+}
+
+// Priority for each rank, highest first
+fn priorities(count: usize) -> Vec<i32> {
+  let bands = count.min(BANDS);
+  (0..count)
+    .map(|rank| (BANDS - rank * bands / count) as i32)
+    .collect()
+}
+
+fn invalid(message: String) -> (StatusCode, String) {
+  (
+    StatusCode::UNPROCESSABLE_ENTITY,
+    json!({"success": false, "message": message}).to_string(),
+  )
+}
+
+// Structs
+impl KnownFields for ReprioritizeReq {
+  const FIELDS: &'static [&'static str] = &["task_ids"];
+  const MAX_ARRAY_LEN: usize = MAX_TASKS;
+}
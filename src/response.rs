@@ -0,0 +1,274 @@
+// Success response builder
+//
+// Every successful response goes through `Reply`, which picks the body shape
+// the client asked for:
+// - `envelope` (default): `{"success": true, "data": ..., "warnings": [...]}`
+// - `raw`: just the resource, with warnings sent as `Warning` headers
+//
+// Clients choose with an Accept profile (`application/json; profile="raw"`)
+// or the `X-Response-Envelope: raw` header. Error bodies always keep the
+// `{"success": false, "message": ...}` shape.
+//
+// Which shape each client gets is recorded for the envelope usage report.
+//
+// Times in the body are written in the date format the client asked for
+// with `X-Date-Format: epoch | epoch-millis | rfc3339` or the matching
+// Accept profile (`profile="rfc3339"`), unix seconds by default (see
+// `Timestamp` in the types crate).
+//
+// `last_modified` adds a Last-Modified header, and `not_modified` answers
+// 304 when the client's If-Modified-Since is not older than it.
+
+// Imports
+use axum::{
+  async_trait,
+  extract::{FromRef, FromRequestParts},
+  http::{header, request::Parts, HeaderValue, StatusCode},
+  response::{IntoResponse, Response},
+};
+
+use serde::Serialize;
+use serde_json::json;
+
+use std::{convert::Infallible, sync::Arc, time::SystemTime};
+
+use axum_crud_rest_types::timestamp::{self, DateFormat};
+
+use crate::{
+  envelope_usage::{EnvelopeUsage, Usage},
+  feature_usage, timing,
+};
+
+// Extractor
+pub struct Reply {
+  envelope: Envelope,
+  date_format: DateFormat,
+  usage: Usage,
+  client: String,
+  usage_log: Arc<EnvelopeUsage>,
+  warnings: Vec<String>,
+  last_modified: Option<SystemTime>,
+  if_modified_since: Option<SystemTime>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Reply
+where
+  Arc<EnvelopeUsage>: FromRef<S>,
+  S: Send + Sync,
+{
+  type Rejection = Infallible;
+
+  async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    let if_modified_since = parts
+      .headers
+      .get(header::IF_MODIFIED_SINCE)
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| httpdate::parse_http_date(value).ok());
+
+    let profiles = profiles(parts);
+    let requested = Envelope::requested(parts, &profiles);
+    let date_format = date_format(parts, &profiles);
+    match requested {
+      Some(Envelope::Wrapped) => feature_usage::used("envelope:explicit"),
+      Some(Envelope::Raw) => feature_usage::used("envelope:raw"),
+      None => {}
+    }
+    match date_format {
+      DateFormat::Epoch => {}
+      DateFormat::EpochMillis => feature_usage::used("date_format:epoch-millis"),
+      DateFormat::Rfc3339 => feature_usage::used("date_format:rfc3339"),
+    }
+    if if_modified_since.is_some() {
+      feature_usage::used("if_modified_since");
+    }
+
+    let usage = match requested {
+      None => Usage::DefaultEnvelope,
+      Some(Envelope::Wrapped) => Usage::ExplicitEnvelope,
+      Some(Envelope::Raw) => Usage::Raw,
+    };
+    let client = parts
+      .headers
+      .get(header::USER_AGENT)
+      .and_then(|value| value.to_str().ok())
+      .unwrap_or("unknown")
+      .to_owned();
+
+    Ok(Reply {
+      envelope: requested.unwrap_or(Envelope::Wrapped),
+      date_format,
+      usage,
+      client,
+      usage_log: Arc::<EnvelopeUsage>::from_ref(state),
+      warnings: Vec::new(),
+      last_modified: None,
+      if_modified_since,
+    })
+  }
+}
+
+impl Reply {
+  pub fn warn(mut self, warnings: Vec<String>) -> Self {
+    self.warnings.extend(warnings);
+    self
+  }
+
+  pub fn last_modified(mut self, last_modified: Option<SystemTime>) -> Self {
+    self.last_modified = last_modified;
+    self
+  }
+
+  // a 304 response if the client already has the current version
+  pub fn not_modified(&self) -> Option<Response> {
+    let last_modified = self.last_modified?;
+    if last_modified > self.if_modified_since? {
+      return None;
+    }
+
+    let mut res = StatusCode::NOT_MODIFIED.into_response();
+    res.headers_mut().insert(
+      header::LAST_MODIFIED,
+      HeaderValue::from_str(&httpdate::fmt_http_date(last_modified)).unwrap(),
+    );
+    Some(res)
+  }
+
+  // serialized straight into the body: going through json! would first
+  // copy the whole response into a serde_json::Value. Fields come out in
+  // declaration order rather than json!'s sorted order.
+  pub fn data(self, status: StatusCode, data: impl Serialize) -> Response {
+    let body = timing::serialize(|| {
+      timestamp::with_format(self.date_format, || match self.envelope {
+        Envelope::Wrapped => serde_json::to_vec(&Wrapped {
+          data,
+          success: true,
+          warnings: &self.warnings,
+        }),
+        Envelope::Raw => serde_json::to_vec(&data),
+      })
+      .unwrap()
+    });
+
+    self.finish(status, body)
+  }
+
+  pub fn done(self, status: StatusCode) -> Response {
+    match self.envelope {
+      Envelope::Wrapped if self.warnings.is_empty() => {
+        self.finish(status, json!({"success": true}).to_string().into_bytes())
+      }
+      Envelope::Wrapped => {
+        let body = json!({"success": true, "warnings": self.warnings}).to_string();
+        self.finish(status, body.into_bytes())
+      }
+      Envelope::Raw => self.finish(StatusCode::NO_CONTENT, Vec::new()),
+    }
+  }
+
+  fn finish(self, status: StatusCode, body: Vec<u8>) -> Response {
+    self.usage_log.record(&self.client, self.usage);
+
+    let mut res = (status, body).into_response();
+    let headers = res.headers_mut();
+
+    if status == StatusCode::NO_CONTENT {
+      headers.remove(header::CONTENT_TYPE);
+    } else {
+      headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(self.envelope.content_type()),
+      );
+    }
+
+    if let Some(last_modified) = self.last_modified {
+      headers.insert(
+        header::LAST_MODIFIED,
+        HeaderValue::from_str(&httpdate::fmt_http_date(last_modified)).unwrap(),
+      );
+    }
+
+    if let Envelope::Raw = self.envelope {
+      for warning in &self.warnings {
+        let value = format!("299 - \"{}\"", warning.replace('"', "'"));
+        if let Ok(value) = HeaderValue::from_str(&value) {
+          headers.append(header::WARNING, value);
+        }
+      }
+    }
+
+    res
+  }
+}
+
+fn header<'a>(parts: &'a Parts, name: &str) -> &'a str {
+  parts
+    .headers
+    .get(name)
+    .and_then(|value| value.to_str().ok())
+    .unwrap_or_default()
+}
+
+// the Accept header's profiles, space-separated values included
+fn profiles(parts: &Parts) -> Vec<&str> {
+  header(parts, header::ACCEPT.as_str())
+    .split(',')
+    .flat_map(|media_type| media_type.split(';').skip(1))
+    .filter_map(|param| param.split_once('='))
+    .filter(|(name, _)| name.trim().eq_ignore_ascii_case("profile"))
+    .flat_map(|(_, value)| value.trim().trim_matches('"').split_whitespace())
+    .collect()
+}
+
+fn date_format(parts: &Parts, profiles: &[&str]) -> DateFormat {
+  DateFormat::parse(header(parts, "x-date-format"))
+    .or_else(|| {
+      profiles
+        .iter()
+        .find_map(|profile| DateFormat::parse(profile))
+    })
+    .unwrap_or_default()
+}
+
+// Structs
+#[derive(Serialize)]
+struct Wrapped<'a, T> {
+  data: T,
+  success: bool,
+  #[serde(skip_serializing_if = "<[String]>::is_empty")]
+  warnings: &'a [String],
+}
+
+#[derive(Clone, Copy)]
+enum Envelope {
+  Wrapped,
+  Raw,
+}
+
+impl Envelope {
+  // None when the client did not ask for either
+  fn requested(parts: &Parts, profiles: &[&str]) -> Option<Self> {
+    let explicit = header(parts, "x-response-envelope");
+    if explicit.eq_ignore_ascii_case("raw") {
+      return Some(Envelope::Raw);
+    }
+    if explicit.eq_ignore_ascii_case("envelope") {
+      return Some(Envelope::Wrapped);
+    }
+
+    if profiles.contains(&"raw") {
+      Some(Envelope::Raw)
+    } else if profiles.contains(&"envelope") {
+      Some(Envelope::Wrapped)
+    } else {
+      None
+    }
+  }
+
+  fn content_type(self) -> &'static str {
+    match self {
+      Envelope::Wrapped => "application/json; profile=\"envelope\"",
+      Envelope::Raw => "application/json; profile=\"raw\"",
+    }
+  }
+}
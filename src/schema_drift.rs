@@ -0,0 +1,91 @@
+// Database schema drift detection
+//
+// schema.txt holds the schema the migrations in this build produce: one
+// line per column, index, constraint and trigger in the public schema,
+// sorted. After migrating, startup compares the live database against it
+// and refuses to serve if they differ, printing the difference:
+// - `- <line>`: expected by the migrations but missing from the database
+// - `+ <line>`: in the database but not created by any migration
+// Without this, a hand-edited database shows up as confusing sqlx errors
+// in whichever handler touches the changed column first.
+//
+// The self-check (and `--check`) runs the same comparison once no
+// migrations are pending. After adding a migration, regenerate the file
+// from a migrated database with `axum_crud_rest schema --dump > schema.txt`.
+
+// Imports
+use sqlx::PgConnection;
+
+use std::collections::BTreeSet;
+
+// Constants
+const EXPECTED: &str = include_str!("../schema.txt");
+
+// Functions
+
+// Lines describing the live schema, sorted
+pub async fn dump(conn: &mut PgConnection) -> Result<Vec<String>, sqlx::Error> {
+  sqlx::query_scalar(
+    r#"
+    SELECT line FROM (
+      SELECT format(
+        'column %s.%s %s%s%s',
+        c.relname,
+        a.attname,
+        format_type(a.atttypid, a.atttypmod),
+        CASE WHEN a.attnotnull THEN ' not null' ELSE '' END,
+        COALESCE(' default ' || pg_get_expr(d.adbin, d.adrelid), '')
+      ) AS line
+      FROM pg_attribute a
+      JOIN pg_class c ON c.oid = a.attrelid
+      JOIN pg_namespace n ON n.oid = c.relnamespace
+      LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+      WHERE n.nspname = 'public' AND c.relkind IN ('r', 'p')
+        AND c.relname <> '_sqlx_migrations'
+        AND a.attnum > 0 AND NOT a.attisdropped
+
+      UNION ALL
+
+      SELECT format('index %s', pg_get_indexdef(i.indexrelid))
+      FROM pg_index i
+      JOIN pg_class c ON c.oid = i.indrelid
+      JOIN pg_namespace n ON n.oid = c.relnamespace
+      WHERE n.nspname = 'public' AND c.relname <> '_sqlx_migrations'
+
+      UNION ALL
+
+      SELECT format('constraint %s.%s %s', c.relname, con.conname, pg_get_constraintdef(con.oid))
+      FROM pg_constraint con
+      JOIN pg_class c ON c.oid = con.conrelid
+      JOIN pg_namespace n ON n.oid = c.relnamespace
+      WHERE n.nspname = 'public' AND c.relname <> '_sqlx_migrations'
+
+      UNION ALL
+
+      SELECT format('trigger %s.%s %s', c.relname, t.tgname, pg_get_triggerdef(t.oid))
+      FROM pg_trigger t
+      JOIN pg_class c ON c.oid = t.tgrelid
+      JOIN pg_namespace n ON n.oid = c.relnamespace
+      WHERE n.nspname = 'public' AND NOT t.tgisinternal
+    ) schema
+    ORDER BY line
+    "#,
+  )
+  .fetch_all(conn)
+  .await
+}
+
+// The differences between schema.txt and the live schema, empty when none
+pub async fn check(conn: &mut PgConnection) -> Result<Vec<String>, sqlx::Error> {
+  let live: BTreeSet<String> = dump(conn).await?.into_iter().collect();
+  let expected: BTreeSet<String> = EXPECTED
+    .lines()
+    .map(str::trim)
+    .filter(|line| !line.is_empty())
+    .map(str::to_owned)
+    .collect();
+
+  let missing = expected.difference(&live).map(|line| format!("- {line}"));
+  let unexpected = live.difference(&expected).map(|line| format!("+ {line}"));
+  Ok(missing.chain(unexpected).collect())
+}
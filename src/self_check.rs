@@ -0,0 +1,268 @@
+// Startup self-check
+//
+// On boot the server checks its surroundings and prints the result as one
+// JSON line (`"event": "self_check"`):
+// - `env`: required environment variables are set
+// - `database`: a connection can be opened
+// - `migrations`: the database has no migrations this build does not know
+//   or that were changed after being applied (pending ones only warn, they
+//   are applied on startup)
+// - `schema`: the live schema matches schema.txt (see the schema_drift
+//   module), once no migrations are pending
+// - `clock`: the local clock agrees with the database's within 5s, which
+//   generated ids and signed export URLs rely on
+// - `consul`: the Consul agent is reachable, when CONSUL_HTTP_ADDR is set
+//
+// `axum_crud_rest --check` runs the same checks and exits non-zero if any
+// failed, for use in deploy pipelines.
+
+// Imports
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use sqlx::{migrate::Migrator, Connection, PgConnection};
+
+use tokio::{net::TcpStream, time::timeout};
+
+use std::{
+  collections::HashMap,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{registration, schema_drift};
+
+// Aliases
+use std::env::var as envar;
+
+// Constants
+const REQUIRED_ENV: &[&str] = &["DATABASE_URL"];
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const CLOCK_SKEW_LIMIT: f64 = 5.0;
+
+// Functions
+pub async fn run(migrator: &Migrator) -> Report {
+  let mut report = Report { checks: Vec::new() };
+
+  let missing: Vec<&str> = REQUIRED_ENV
+    .iter()
+    .copied()
+    .filter(|name| envar(name).is_err())
+    .collect();
+  if missing.is_empty() {
+    report.push("env", Status::Pass, "required variables are set".to_owned());
+  } else {
+    report.push(
+      "env",
+      Status::Fail,
+      format!("missing {}", missing.join(", ")),
+    );
+  }
+
+  match connect().await {
+    Ok(mut conn) => {
+      report.push("database", Status::Pass, "connected".to_owned());
+
+      let (status, detail) = check_migrations(&mut conn, migrator).await;
+      let migrated = status == Status::Pass;
+      report.push("migrations", status, detail);
+
+      let (status, detail) = if migrated {
+        check_schema(&mut conn).await
+      } else {
+        (Status::Skip, "migrations are not up to date".to_owned())
+      };
+      report.push("schema", status, detail);
+
+      let (status, detail) = check_clock(&mut conn).await;
+      report.push("clock", status, detail);
+
+      let _ = conn.close().await;
+    }
+    Err(e) => {
+      report.push("database", Status::Fail, e);
+      report.push(
+        "migrations",
+        Status::Skip,
+        "no database connection".to_owned(),
+      );
+      report.push("schema", Status::Skip, "no database connection".to_owned());
+      report.push("clock", Status::Skip, "no database connection".to_owned());
+    }
+  }
+
+  match envar("CONSUL_HTTP_ADDR") {
+    Ok(consul_addr) => match registration::agent_addr(&consul_addr) {
+      Ok(addr) => match timeout(CHECK_TIMEOUT, TcpStream::connect(&addr)).await {
+        Ok(Ok(_)) => report.push("consul", Status::Pass, format!("reached {consul_addr}")),
+        Ok(Err(e)) => report.push("consul", Status::Fail, format!("{consul_addr}: {e}")),
+        Err(_) => report.push("consul", Status::Fail, format!("{consul_addr}: timed out")),
+      },
+      Err(e) => report.push("consul", Status::Fail, e.to_string()),
+    },
+    Err(_) => report.push(
+      "consul",
+      Status::Skip,
+      "CONSUL_HTTP_ADDR is not set".to_owned(),
+    ),
+  }
+
+  report
+}
+
+async fn connect() -> Result<PgConnection, String> {
+  let database_url = envar("DATABASE_URL").map_err(|_| "DATABASE_URL is not set".to_owned())?;
+
+  match timeout(CHECK_TIMEOUT, PgConnection::connect(&database_url)).await {
+    Ok(result) => result.map_err(|e| e.to_string()),
+    Err(_) => Err("timed out".to_owned()),
+  }
+}
+
+async fn check_migrations(conn: &mut PgConnection, migrator: &Migrator) -> (Status, String) {
+  let applied: Result<Vec<(i64, Vec<u8>)>, sqlx::Error> = async {
+    let exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+      .fetch_one(&mut *conn)
+      .await?;
+    if !exists {
+      return Ok(Vec::new());
+    }
+
+    sqlx::query_as("SELECT version, checksum FROM _sqlx_migrations WHERE success")
+      .fetch_all(&mut *conn)
+      .await
+  }
+  .await;
+
+  let applied: HashMap<i64, Vec<u8>> = match applied {
+    Ok(applied) => applied.into_iter().collect(),
+    Err(e) => return (Status::Fail, e.to_string()),
+  };
+
+  let unknown: Vec<String> = applied
+    .keys()
+    .filter(|version| !migrator.version_exists(**version))
+    .map(i64::to_string)
+    .collect();
+  if !unknown.is_empty() {
+    return (
+      Status::Fail,
+      format!(
+        "applied migrations unknown to this build: {}",
+        unknown.join(", ")
+      ),
+    );
+  }
+
+  let changed: Vec<String> = migrator
+    .iter()
+    .filter(|m| {
+      applied
+        .get(&m.version)
+        .is_some_and(|checksum| *checksum != *m.checksum)
+    })
+    .map(|m| m.version.to_string())
+    .collect();
+  if !changed.is_empty() {
+    return (
+      Status::Fail,
+      format!(
+        "migrations changed after being applied: {}",
+        changed.join(", ")
+      ),
+    );
+  }
+
+  let pending = migrator
+    .iter()
+    .filter(|m| !applied.contains_key(&m.version))
+    .count();
+  match pending {
+    0 => (Status::Pass, "up to date".to_owned()),
+    n => (Status::Warn, format!("{n} pending, applied on startup")),
+  }
+}
+
+async fn check_schema(conn: &mut PgConnection) -> (Status, String) {
+  match schema_drift::check(conn).await {
+    Ok(drift) if drift.is_empty() => (Status::Pass, "matches schema.txt".to_owned()),
+    Ok(drift) => (
+      Status::Fail,
+      format!("differs from schema.txt: {}", drift.join("; ")),
+    ),
+    Err(e) => (Status::Fail, e.to_string()),
+  }
+}
+
+async fn check_clock(conn: &mut PgConnection) -> (Status, String) {
+  let db_now: f64 = match sqlx::query_scalar("SELECT EXTRACT(EPOCH FROM now())::float8")
+    .fetch_one(&mut *conn)
+    .await
+  {
+    Ok(db_now) => db_now,
+    Err(e) => return (Status::Fail, e.to_string()),
+  };
+
+  let local_now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0.0, |d| d.as_secs_f64());
+  let skew = local_now - db_now;
+
+  if skew.abs() > CLOCK_SKEW_LIMIT {
+    (Status::Fail, format!("{skew:.3}s off the database clock"))
+  } else {
+    (Status::Pass, format!("{skew:.3}s off the database clock"))
+  }
+}
+
+// Structs
+pub struct Report {
+  checks: Vec<Check>,
+}
+
+impl Report {
+  pub fn passed(&self) -> bool {
+    self.checks.iter().all(|check| check.status != Status::Fail)
+  }
+
+  pub fn print(&self) {
+    println!(
+      "{}",
+      json!({
+        "event": "self_check",
+        "passed": self.passed(),
+        "checks": self.checks,
+      })
+    );
+  }
+
+  pub fn to_json(&self) -> Value {
+    json!({
+      "passed": self.passed(),
+      "checks": self.checks,
+    })
+  }
+
+  fn push(&mut self, name: &'static str, status: Status, detail: String) {
+    self.checks.push(Check {
+      name,
+      status,
+      detail,
+    });
+  }
+}
+
+#[derive(Serialize)]
+struct Check {
+  name: &'static str,
+  status: Status,
+  detail: String,
+}
+
+#[derive(Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+  Pass,
+  Warn,
+  Fail,
+  Skip,
+}
@@ -0,0 +1,121 @@
+// Task statistics
+//
+// GET /tasks/stats counts all tasks, open ones, claimed (open and under a
+// worker's lease), completed ones and those created in the last hour.
+// Dashboards poll it on auto-refresh, so the counts are cached for
+// TASK_STATS_TTL_SECS (default 5) and then served stale while they are
+// revalidated: the first request after they expire starts one refresh in
+// the background, and it and every request until the refresh lands get the
+// stale counts. However many dashboards are open, the aggregate runs at most
+// once per TTL. `computed_at` says how old the counts are. The counts come
+// from the same aggregate as the /metrics task gauges, which each refresh
+// updates too.
+//
+// Only the first requests after startup wait for the query, sharing a
+// single one. A failed refresh keeps the stale counts and is logged as one
+// JSON line (`"event": "task_stats_refresh_failed"`); the next request
+// after that tries again.
+
+// Imports
+use axum::{extract::State, http::StatusCode, response::Response};
+
+use serde_json::json;
+
+use sqlx::PgPool;
+
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use axum_crud_rest_types::TaskStats;
+
+use crate::{
+  db,
+  metrics::{self, Metrics},
+  response::Reply,
+};
+
+// Store
+pub struct StatsCache {
+  ttl: Duration,
+  cached: Mutex<Option<(Instant, TaskStats)>>,
+  refreshing: AtomicBool,
+  // held while the first counts are computed
+  first: tokio::sync::Mutex<()>,
+}
+
+impl StatsCache {
+  pub fn new(ttl: Duration) -> Self {
+    Self {
+      ttl,
+      cached: Mutex::new(None),
+      refreshing: AtomicBool::new(false),
+      first: tokio::sync::Mutex::new(()),
+    }
+  }
+
+  fn cached(&self) -> Option<(Instant, TaskStats)> {
+    self.cached.lock().unwrap().clone()
+  }
+}
+
+// Functions
+pub async fn get_task_stats(
+  State(pg_pool): State<PgPool>,
+  State(metrics): State<Arc<Metrics>>,
+  State(cache): State<Arc<StatsCache>>,
+  reply: Reply,
+) -> Result<Response, (StatusCode, String)> {
+  let stats = match cache.cached() {
+    Some((computed, stats)) => {
+      if computed.elapsed() >= cache.ttl && !cache.refreshing.swap(true, Ordering::AcqRel) {
+        // outside the request, so its deadline does not cut the refresh short
+        tokio::spawn(async move {
+          let _refreshing = Refreshing(&cache.refreshing);
+          if let Err(e) = refresh(&pg_pool, &metrics, &cache).await {
+            eprintln!(
+              "{}",
+              json!({"event": "task_stats_refresh_failed", "error": e.to_string()})
+            );
+          }
+        });
+      }
+      stats
+    }
+    None => {
+      let _first = cache.first.lock().await;
+      match cache.cached() {
+        Some((_, stats)) => stats,
+        None => refresh(&pg_pool, &metrics, &cache)
+          .await
+          .map_err(db::error_response)?,
+      }
+    }
+  };
+
+  Ok(reply.data(StatusCode::OK, stats))
+}
+
+async fn refresh(
+  pg_pool: &PgPool,
+  metrics: &Metrics,
+  cache: &StatsCache,
+) -> Result<TaskStats, sqlx::Error> {
+  let stats = metrics::aggregate_task_stats(pg_pool, metrics).await?;
+  *cache.cached.lock().unwrap() = Some((Instant::now(), stats.clone()));
+  Ok(stats)
+}
+
+// Structs
+// lets the next request start a refresh once this one ends, even if it panicked
+struct Refreshing<'a>(&'a AtomicBool);
+
+impl Drop for Refreshing<'_> {
+  fn drop(&mut self) {
+    self.0.store(false, Ordering::Release);
+  }
+}
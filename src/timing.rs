@@ -0,0 +1,112 @@
+// Server-Timing breakdown
+//
+// With SERVER_TIMING enabled every response carries a `Server-Timing`
+// header that browser devtools show next to the request:
+// - `db`: time spent acquiring connections and running statements, with
+//   the number of statements as its description
+// - `serialize`: time spent turning response data into JSON
+// - `app`: everything else, i.e. handler logic and middleware
+// - `total`: the whole request as seen by this middleware
+//
+// Database work is timed where it is awaited (`db`), statements are counted
+// when they are recorded for diagnostics (`count_query`), serialization is
+// timed in `Reply` (`serialize`). The numbers are collected for every
+// request so the metrics middleware can report them via `db_usage`; only
+// the header depends on SERVER_TIMING.
+
+// Imports
+use axum::{
+  extract::{Request, State},
+  http::HeaderValue,
+  middleware::Next,
+  response::Response,
+};
+
+use std::{
+  future::Future,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use crate::AppState;
+
+tokio::task_local! {
+  static TIMINGS: Arc<Mutex<Timings>>;
+}
+
+// Functions
+pub async fn server_timing(State(state): State<AppState>, req: Request, next: Next) -> Response {
+  let timings = Arc::new(Mutex::new(Timings::default()));
+
+  let started = Instant::now();
+  let mut res = TIMINGS.scope(timings.clone(), next.run(req)).await;
+  let total = started.elapsed();
+
+  if !state.server_timing {
+    return res;
+  }
+
+  let timings = timings.lock().unwrap();
+  let app = total.saturating_sub(timings.db + timings.serialize);
+  let value = format!(
+    "db;dur={:.3};desc=\"{} queries\", serialize;dur={:.3}, app;dur={:.3}, total;dur={:.3}",
+    millis(timings.db),
+    timings.queries,
+    millis(timings.serialize),
+    millis(app),
+    millis(total)
+  );
+
+  if let Ok(value) = HeaderValue::from_str(&value) {
+    res.headers_mut().insert("server-timing", value);
+  }
+
+  res
+}
+
+// Runs a database future, adding its duration to the request's db time
+pub async fn db<F: Future>(fut: F) -> F::Output {
+  let started = Instant::now();
+  let output = fut.await;
+  add(started.elapsed(), |timings| &mut timings.db);
+  output
+}
+
+// Runs a serialization step, adding its duration to the request's serialize time
+pub fn serialize<T>(f: impl FnOnce() -> T) -> T {
+  let started = Instant::now();
+  let output = f();
+  add(started.elapsed(), |timings| &mut timings.serialize);
+  output
+}
+
+// Counts a statement against the current request
+pub fn count_query() {
+  let _ = TIMINGS.try_with(|timings| timings.lock().unwrap().queries += 1);
+}
+
+// Statements run and database time so far in the current request
+pub fn db_usage() -> Option<(u64, Duration)> {
+  TIMINGS
+    .try_with(|timings| {
+      let timings = timings.lock().unwrap();
+      (timings.queries, timings.db)
+    })
+    .ok()
+}
+
+fn add(elapsed: Duration, bucket: impl FnOnce(&mut Timings) -> &mut Duration) {
+  let _ = TIMINGS.try_with(|timings| *bucket(&mut timings.lock().unwrap()) += elapsed);
+}
+
+fn millis(duration: Duration) -> f64 {
+  duration.as_secs_f64() * 1000.0
+}
+
+// Structs
+#[derive(Default)]
+struct Timings {
+  queries: u64,
+  db: Duration,
+  serialize: Duration,
+}
@@ -0,0 +1,42 @@
+// Task list tombstones
+//
+// A row that leaves the task list, deleted or updated out of a filter,
+// leaves a tombstone in task_list_tombstones holding its old name, priority
+// and external_id (see migration 0010). GET /tasks computes Last-Modified
+// from the rows and tombstones its filters match, so a write moves only the
+// lists it touches and a list that lost a row still sees a newer time.
+//
+// Tombstones are kept for TOMBSTONE_RETENTION and then removed by the
+// `prune_tombstones` job, which raises `task_list_changes.pruned_until` to
+// the newest one it removed. Every list counts that floor, so pruning never
+// moves a Last-Modified back in time; it only bumps all lists once per run.
+
+// Imports
+use sqlx::PgPool;
+
+use std::time::Duration;
+
+// Constants
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(600);
+const TOMBSTONE_RETENTION: &str = "1 hour";
+
+// Functions
+pub async fn prune_tombstones(pg_pool: &PgPool) -> Result<(), String> {
+  sqlx::query!(
+    "
+    WITH pruned AS (
+      DELETE FROM task_list_tombstones
+      WHERE removed_at < now() - $1::TEXT::INTERVAL
+      RETURNING removed_at
+    )
+    UPDATE task_list_changes
+    SET pruned_until = GREATEST(pruned_until, (SELECT MAX(removed_at) FROM pruned))
+    ",
+    TOMBSTONE_RETENTION
+  )
+  .execute(pg_pool)
+  .await
+  .map_err(|e| e.to_string())?;
+
+  Ok(())
+}
@@ -0,0 +1,104 @@
+// Shared request and response types
+//
+// The task API's request bodies and response rows, and the soft validation
+// rules applied to them, with the `Timestamp` type every response writes
+// times through and the paths of every route. Nothing here depends on the
+// server, so frontends built for wasm32 (Yew, Leptos) can use the same
+// definitions. The `sqlx` feature adds FromRow to the rows the server reads
+// from the database, and `clock` adds `Timestamp::now`, which reads the
+// system clock that wasm32-unknown-unknown does not have.
+
+// Modules
+pub mod routes;
+pub mod timestamp;
+pub mod validation;
+
+// Imports
+use serde::{Deserialize, Serialize};
+
+use timestamp::Timestamp;
+
+// Structs
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct TaskRow {
+  pub task_id: i64,
+  pub name: String,
+  pub priority: Option<i32>,
+  pub external_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateTaskReq {
+  pub name: String,
+  pub priority: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateTaskRow {
+  pub task_id: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpsertTaskReq {
+  pub external_id: String,
+  pub name: String,
+  pub priority: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateTaskReq {
+  pub name: Option<String>,
+  pub priority: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QuickAddReq {
+  pub text: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QuickTask {
+  pub task_id: i64,
+  pub name: String,
+  pub priority: Option<i32>,
+  pub external_id: Option<String>,
+  pub due_at: Option<Timestamp>,
+  pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReprioritizeReq {
+  pub task_ids: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeaseReq {
+  pub worker: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClaimedTask {
+  pub task_id: i64,
+  pub name: String,
+  pub priority: Option<i32>,
+  pub external_id: Option<String>,
+  pub claimed_by: String,
+  pub lease_expires_at: Timestamp,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RenewedLease {
+  pub task_id: i64,
+  pub lease_expires_at: Timestamp,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TaskStats {
+  pub total: i64,
+  pub open: i64,
+  pub claimed: i64,
+  pub completed: i64,
+  pub created_last_hour: i64,
+  pub computed_at: Timestamp,
+}
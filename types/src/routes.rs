@@ -0,0 +1,110 @@
+// Route paths
+//
+// Every path the API serves, as the axum route template the server
+// registers, plus a builder per template with parameters that fills them
+// in (percent-encoding string values), for links in responses and for
+// clients. Admin paths are relative to `ADMIN`, where the server nests
+// them.
+
+// Constants
+pub const ROOT: &str = "/";
+pub const TASKS: &str = "/tasks";
+pub const TASKS_QUICK: &str = "/tasks/quick";
+pub const TASKS_REPRIORITIZE: &str = "/tasks/reprioritize";
+pub const TASKS_NEXT: &str = "/tasks/next";
+pub const TASKS_STREAM: &str = "/tasks/stream";
+pub const TASKS_STATS: &str = "/tasks/stats";
+pub const TASK: &str = "/tasks/:task_id";
+pub const TASK_COMPLETE: &str = "/tasks/:task_id/complete";
+pub const TASK_LEASE_RENEW: &str = "/tasks/:task_id/lease/renew";
+pub const EXPORTS: &str = "/exports";
+pub const EXPORT_DOWNLOAD: &str = "/exports/:export_id/download";
+pub const OPERATION: &str = "/operations/:operation_id";
+pub const OPERATION_CANCEL: &str = "/operations/:operation_id/cancel";
+pub const METRICS: &str = "/metrics";
+pub const ADMIN: &str = "/admin";
+
+// Modules
+pub mod admin {
+  use super::fill;
+
+  // Constants
+  pub const QUERY_PLANS: &str = "/query-plans";
+  pub const ENVELOPE_USAGE: &str = "/envelope-usage";
+  pub const FEATURE_USAGE: &str = "/feature-usage";
+  pub const JOBS: &str = "/jobs";
+  pub const JOB_RUN: &str = "/jobs/:name/run";
+  pub const JOB_PAUSE: &str = "/jobs/:name/pause";
+  pub const JOB_RESUME: &str = "/jobs/:name/resume";
+  pub const HEALTH_HISTORY: &str = "/health/history";
+  pub const SUPPORT_BUNDLE: &str = "/support-bundle";
+  pub const TABLES: &str = "/tables";
+  pub const TABLE: &str = "/tables/:name";
+
+  // Functions
+  pub fn job_run(name: &str) -> String {
+    format!("{}{}", super::ADMIN, fill(JOB_RUN, &[name]))
+  }
+
+  pub fn job_pause(name: &str) -> String {
+    format!("{}{}", super::ADMIN, fill(JOB_PAUSE, &[name]))
+  }
+
+  pub fn job_resume(name: &str) -> String {
+    format!("{}{}", super::ADMIN, fill(JOB_RESUME, &[name]))
+  }
+
+  pub fn table(name: &str) -> String {
+    format!("{}{}", super::ADMIN, fill(TABLE, &[name]))
+  }
+}
+
+// Functions
+pub fn task(task_id: i64) -> String {
+  fill(TASK, &[&task_id.to_string()])
+}
+
+pub fn task_complete(task_id: i64) -> String {
+  fill(TASK_COMPLETE, &[&task_id.to_string()])
+}
+
+pub fn task_lease_renew(task_id: i64) -> String {
+  fill(TASK_LEASE_RENEW, &[&task_id.to_string()])
+}
+
+pub fn export_download(export_id: u64) -> String {
+  fill(EXPORT_DOWNLOAD, &[&export_id.to_string()])
+}
+
+pub fn operation(operation_id: u64) -> String {
+  fill(OPERATION, &[&operation_id.to_string()])
+}
+
+pub fn operation_cancel(operation_id: u64) -> String {
+  fill(OPERATION_CANCEL, &[&operation_id.to_string()])
+}
+
+// Replaces the template's `:param` segments with `values`, in order
+fn fill(template: &str, values: &[&str]) -> String {
+  let mut values = values.iter();
+  template
+    .split('/')
+    .map(|segment| match segment.strip_prefix(':') {
+      Some(_) => encode(values.next().expect("a value for every route parameter")),
+      None => segment.to_owned(),
+    })
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+fn encode(value: &str) -> String {
+  value
+    .bytes()
+    .map(|b| match b {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+        (b as char).to_string()
+      }
+      b => format!("%{b:02X}"),
+    })
+    .collect()
+}
@@ -0,0 +1,235 @@
+// Timestamps in the client's date format
+//
+// Every point in time the API returns is a `Timestamp`. How it is written
+// is not fixed per field but chosen for the whole response with
+// `with_format`:
+// - `epoch` (default): whole seconds since the unix epoch
+// - `epoch-millis`: milliseconds since the unix epoch
+// - `rfc3339`: a UTC date string with an explicit offset,
+//   `2026-10-14T09:30:00.000+00:00`
+//
+// Deserializing reads numbers in the current format's unit (seconds unless
+// `epoch-millis`) and RFC 3339 strings with any offset, so clients decode
+// with the same format they requested.
+
+// Imports
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use std::{
+  cell::Cell,
+  fmt,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+thread_local! {
+  static FORMAT: Cell<DateFormat> = const { Cell::new(DateFormat::Epoch) };
+}
+
+// Functions
+
+// Runs `f` with timestamps written in `format`
+pub fn with_format<T>(format: DateFormat, f: impl FnOnce() -> T) -> T {
+  let previous = FORMAT.with(|current| current.replace(format));
+  let output = f();
+  FORMAT.with(|current| current.set(previous));
+  output
+}
+
+// Howard Hinnant's days_from_civil / civil_from_days, for the proleptic
+// Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = year.div_euclid(400);
+  let yoe = year.rem_euclid(400);
+  let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+  let days = days + 719468;
+  let era = days.div_euclid(146097);
+  let doe = days.rem_euclid(146097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + i64::from(month <= 2);
+  (year, month, day)
+}
+
+fn format_rfc3339(millis: i64) -> String {
+  let secs = millis.div_euclid(1000);
+  let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+  let secs_of_day = secs.rem_euclid(86400);
+
+  format!(
+    "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}+00:00",
+    secs_of_day / 3600,
+    secs_of_day / 60 % 60,
+    secs_of_day % 60,
+    millis.rem_euclid(1000)
+  )
+}
+
+// `YYYY-MM-DDTHH:MM:SS[.fraction](Z|+HH:MM|-HH:MM)`
+fn parse_rfc3339(value: &str) -> Option<i64> {
+  let bytes = value.as_bytes();
+  let number = |range: std::ops::Range<usize>| -> Option<i64> {
+    let digits = value.get(range)?;
+    digits
+      .bytes()
+      .all(|b| b.is_ascii_digit())
+      .then(|| digits.parse().ok())?
+  };
+
+  if bytes.len() < 20
+    || bytes[4] != b'-'
+    || bytes[7] != b'-'
+    || !matches!(bytes[10], b'T' | b't')
+    || bytes[13] != b':'
+    || bytes[16] != b':'
+  {
+    return None;
+  }
+
+  let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+  let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+  if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+    return None;
+  }
+  // 60 allows for leap seconds
+  if second > 60 {
+    return None;
+  }
+
+  let mut rest = &value[19..];
+  let mut millis = 0;
+  if let Some(fraction) = rest.strip_prefix('.') {
+    let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+    if len == 0 {
+      return None;
+    }
+    let digits = format!("{:0<3}", &fraction[..len.min(3)]);
+    millis = digits.parse().ok()?;
+    rest = &fraction[len..];
+  }
+
+  let offset_minutes = match rest.as_bytes() {
+    [b'Z' | b'z'] => 0,
+    [sign @ (b'+' | b'-'), _, _, b':', _, _] => {
+      let start = value.len() - 5;
+      let (hours, minutes) = (number(start..start + 2)?, number(start + 3..start + 5)?);
+      if hours > 23 || minutes > 59 {
+        return None;
+      }
+      let offset = hours * 60 + minutes;
+      if *sign == b'-' {
+        -offset
+      } else {
+        offset
+      }
+    }
+    _ => return None,
+  };
+
+  let days = days_from_civil(year, month, day);
+  let secs = days * 86400 + hour * 3600 + minute * 60 + second - offset_minutes * 60;
+  Some(secs * 1000 + millis)
+}
+
+// Structs
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum DateFormat {
+  #[default]
+  Epoch,
+  EpochMillis,
+  Rfc3339,
+}
+
+impl DateFormat {
+  pub fn parse(name: &str) -> Option<Self> {
+    match name.trim().to_ascii_lowercase().as_str() {
+      "epoch" => Some(DateFormat::Epoch),
+      "epoch-millis" => Some(DateFormat::EpochMillis),
+      "rfc3339" => Some(DateFormat::Rfc3339),
+      _ => None,
+    }
+  }
+}
+
+// milliseconds since the unix epoch
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Timestamp(pub i64);
+
+impl Timestamp {
+  #[cfg(feature = "clock")]
+  pub fn now() -> Self {
+    Self::from(SystemTime::now())
+  }
+
+  pub fn from_secs(secs: i64) -> Self {
+    Self(secs.saturating_mul(1000))
+  }
+
+  // midnight UTC at the start of a calendar date, if the date exists
+  pub fn from_utc_date(year: i64, month: i64, day: i64) -> Option<Self> {
+    let days = days_from_civil(year, month, day);
+    (civil_from_days(days) == (year, month, day)).then(|| Self(days * 86_400_000))
+  }
+}
+
+impl From<SystemTime> for Timestamp {
+  fn from(time: SystemTime) -> Self {
+    let millis = match time.duration_since(UNIX_EPOCH) {
+      Ok(since) => since.as_millis() as i64,
+      Err(e) => -(e.duration().as_millis() as i64),
+    };
+    Self(millis)
+  }
+}
+
+impl Serialize for Timestamp {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match FORMAT.with(Cell::get) {
+      DateFormat::Epoch => serializer.serialize_i64(self.0.div_euclid(1000)),
+      DateFormat::EpochMillis => serializer.serialize_i64(self.0),
+      DateFormat::Rfc3339 => serializer.serialize_str(&format_rfc3339(self.0)),
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_any(TimestampVisitor)
+  }
+}
+
+struct TimestampVisitor;
+
+impl de::Visitor<'_> for TimestampVisitor {
+  type Value = Timestamp;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a unix timestamp or an RFC 3339 date")
+  }
+
+  fn visit_i64<E: de::Error>(self, value: i64) -> Result<Timestamp, E> {
+    match FORMAT.with(Cell::get) {
+      DateFormat::EpochMillis => Ok(Timestamp(value)),
+      _ => Ok(Timestamp::from_secs(value)),
+    }
+  }
+
+  fn visit_u64<E: de::Error>(self, value: u64) -> Result<Timestamp, E> {
+    let value = i64::try_from(value).map_err(|_| E::custom("timestamp out of range"))?;
+    self.visit_i64(value)
+  }
+
+  fn visit_str<E: de::Error>(self, value: &str) -> Result<Timestamp, E> {
+    parse_rfc3339(value)
+      .map(Timestamp)
+      .ok_or_else(|| E::custom(format!("invalid RFC 3339 date `{value}`")))
+  }
+}
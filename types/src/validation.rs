@@ -0,0 +1,36 @@
+// Soft validation of task fields
+//
+// Write handlers pass incoming fields through these checks before saving.
+// Values that can be fixed up are corrected and reported as warnings in the
+// success response instead of failing the request:
+// - priorities outside 1 to 5 are clamped into range
+// - names lose leading and trailing whitespace
+//
+// Tasks have no due dates yet, so there is nothing to check for those.
+
+// Constants
+const PRIORITY_MIN: i32 = 1;
+const PRIORITY_MAX: i32 = 5;
+
+// Functions
+pub fn priority(priority: Option<i32>, warnings: &mut Vec<String>) -> Option<i32> {
+  let priority = priority?;
+  let clamped = priority.clamp(PRIORITY_MIN, PRIORITY_MAX);
+
+  if clamped != priority {
+    warnings.push(format!("priority {priority} clamped to {clamped}"));
+  }
+
+  Some(clamped)
+}
+
+pub fn name(name: String, warnings: &mut Vec<String>) -> String {
+  let trimmed = name.trim();
+
+  if trimmed.len() == name.len() {
+    return name;
+  }
+
+  warnings.push("name had surrounding whitespace, which was removed".to_owned());
+  trimmed.to_owned()
+}